@@ -0,0 +1,288 @@
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+use bevy::math::vec2;
+use bevy::prelude::*;
+use bevy::render::render_resource::TextureFormat;
+use bevy::render::texture::{CompressedImageFormats, ImageSampler, ImageType, TextureError};
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+use serde::{Deserialize, Serialize};
+
+use crate::*;
+
+pub struct BiomePlugin;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Biome {
+    Plains,
+    Fertile,
+    Desert,
+    Swamp,
+}
+
+/// Grid of biomes stretched over the world, row 0 at the bottom
+#[derive(Resource)]
+pub struct BiomeMap {
+    /// Source and world size the grid was built from
+    source: BiomeMapSource,
+    world: Vec2,
+    cols: usize,
+    rows: usize,
+    cells: Vec<Biome>,
+}
+
+#[derive(Debug)]
+pub enum BiomeError {
+    Io(std::io::Error),
+    Image(TextureError),
+    Format,
+}
+
+impl Plugin for BiomePlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<BiomeMap>().add_systems(
+            FixedUpdate,
+            sync_biome_map
+                .in_set(SimSet::World)
+                .run_if(in_state(SimState::Simulating)),
+        );
+    }
+}
+
+/// Rebuilds the biome grid when `SimConfig::biome_map` or the world size changes
+pub fn sync_biome_map(config: Res<SimConfig>, mut biomes: ResMut<BiomeMap>) {
+    let world = vec2(config.world_w, config.world_h);
+    if biomes.source == config.biome_map && biomes.world == world {
+        return;
+    }
+
+    *biomes = match BiomeMap::build(&config) {
+        Ok(map) => map,
+        Err(e) => {
+            error!(
+                "Failed to build the biome map from {:?}: {}, using plains instead",
+                config.biome_map, e
+            );
+            BiomeMap {
+                source: config.biome_map.clone(),
+                world,
+                ..default()
+            }
+        }
+    };
+}
+
+impl Biome {
+    pub const ALL: [Biome; 4] = [Biome::Plains, Biome::Fertile, Biome::Desert, Biome::Swamp];
+
+    pub fn params(self, config: &SimConfig) -> &BiomeParams {
+        match self {
+            Biome::Plains => &config.biome_plains,
+            Biome::Fertile => &config.biome_fertile,
+            Biome::Desert => &config.biome_desert,
+            Biome::Swamp => &config.biome_swamp,
+        }
+    }
+}
+
+impl Default for BiomeMap {
+    fn default() -> Self {
+        Self {
+            source: BiomeMapSource::Uniform,
+            world: Vec2::ZERO,
+            cols: 1,
+            rows: 1,
+            cells: vec![Biome::Plains],
+        }
+    }
+}
+
+impl BiomeMap {
+    pub fn build(config: &SimConfig) -> Result<Self, BiomeError> {
+        let world = vec2(config.world_w, config.world_h);
+        let (cols, rows, cells) = match &config.biome_map {
+            BiomeMapSource::Uniform => (1, 1, vec![Biome::Plains]),
+            BiomeMapSource::Procedural {
+                seed,
+                cell_size,
+                scale,
+            } => {
+                let cols = ((world.x * 2.0 / cell_size).ceil() as usize).max(1);
+                let rows = ((world.y * 2.0 / cell_size).ceil() as usize).max(1);
+                (cols, rows, generate(*seed, cols, rows, cell_size / scale))
+            }
+            BiomeMapSource::Image { path } => load_image(Path::new(path), config)?,
+        };
+
+        Ok(Self {
+            source: config.biome_map.clone(),
+            world,
+            cols,
+            rows,
+            cells,
+        })
+    }
+
+    /// Single cell covering the whole world
+    pub fn is_uniform(&self) -> bool {
+        self.cells.len() == 1
+    }
+
+    pub fn size(&self) -> (usize, usize) {
+        (self.cols, self.rows)
+    }
+
+    /// Every cell row by row, starting at the bottom left
+    pub fn cells(&self) -> &[Biome] {
+        &self.cells
+    }
+
+    /// Biome at `pos`, positions outside the world get the closest edge cell
+    pub fn at(&self, pos: Vec2) -> Biome {
+        let cell = |offset: f32, size: f32, num_cells: usize| {
+            let i = ((offset + size) / (size * 2.0) * num_cells as f32).floor();
+            (i.max(0.0) as usize).min(num_cells - 1)
+        };
+        let col = cell(pos.x, self.world.x, self.cols);
+        let row = cell(pos.y, self.world.y, self.rows);
+        self.cells[row * self.cols + col]
+    }
+
+    pub fn params<'a>(&self, pos: Vec2, config: &'a SimConfig) -> &'a BiomeParams {
+        self.at(pos).params(config)
+    }
+
+    /// Sets every cell with its center within `radius` of `center`,
+    /// a uniform map is first split into cells of `BIOME_PAINT_CELL_SIZE`
+    pub fn paint(&mut self, center: Vec2, radius: f32, biome: Biome) {
+        if self.is_uniform() {
+            self.cols = ((self.world.x * 2.0 / BIOME_PAINT_CELL_SIZE).ceil() as usize).max(1);
+            self.rows = ((self.world.y * 2.0 / BIOME_PAINT_CELL_SIZE).ceil() as usize).max(1);
+            self.cells = vec![self.cells[0]; self.cols * self.rows];
+        }
+
+        let cell_size = self.world * 2.0 / vec2(self.cols as f32, self.rows as f32);
+        for row in 0..self.rows {
+            for col in 0..self.cols {
+                let cell_center = -self.world + (vec2(col as f32, row as f32) + 0.5) * cell_size;
+                if cell_center.distance_squared(center) <= radius * radius {
+                    self.cells[row * self.cols + col] = biome;
+                }
+            }
+        }
+    }
+}
+
+/// Two smooth noise fields, moisture and fertility, sampled at each cell center.
+/// `frequency` is the number of noise lattice cells per biome cell.
+fn generate(seed: u64, cols: usize, rows: usize, frequency: f32) -> Vec<Biome> {
+    let mut rng = ChaCha8Rng::seed_from_u64(seed);
+    let (width, height) = (cols as f32 * frequency, rows as f32 * frequency);
+    let mut field = || {
+        let coarse = ValueNoise::new(&mut rng, width, height);
+        let fine = ValueNoise::new(&mut rng, width * 2.0, height * 2.0);
+        // A finer octave at half weight breaks up the lattice lines
+        move |x: f32, y: f32| (coarse.sample(x, y) + fine.sample(x * 2.0, y * 2.0) * 0.5) / 1.5
+    };
+    let moisture = field();
+    let fertility = field();
+
+    let mut cells = Vec::with_capacity(cols * rows);
+    for row in 0..rows {
+        for col in 0..cols {
+            let (x, y) = (
+                (col as f32 + 0.5) * frequency,
+                (row as f32 + 0.5) * frequency,
+            );
+            let (moisture, fertility) = (moisture(x, y), fertility(x, y));
+            cells.push(if moisture > 0.62 {
+                Biome::Swamp
+            } else if fertility > 0.58 {
+                Biome::Fertile
+            } else if fertility < 0.42 && moisture < 0.45 {
+                Biome::Desert
+            } else {
+                Biome::Plains
+            });
+        }
+    }
+
+    cells
+}
+
+/// Random values on a unit lattice covering `width` x `height`, smoothly interpolated in between
+struct ValueNoise {
+    cols: usize,
+    values: Vec<f32>,
+}
+
+impl ValueNoise {
+    fn new(rng: &mut ChaCha8Rng, width: f32, height: f32) -> Self {
+        let cols = width.ceil() as usize + 2;
+        let rows = height.ceil() as usize + 2;
+        Self {
+            cols,
+            values: (0..cols * rows).map(|_| rng.gen_range(0.0..1.0)).collect(),
+        }
+    }
+
+    fn sample(&self, x: f32, y: f32) -> f32 {
+        let (col, row) = (x.floor() as usize, y.floor() as usize);
+        // Smoothstep keeps the lattice from showing up as straight lines
+        let smooth = |t: f32| t * t * (3.0 - 2.0 * t);
+        let (tx, ty) = (smooth(x.fract()), smooth(y.fract()));
+        let value = |col: usize, row: usize| self.values[row * self.cols + col];
+        let bottom = value(col, row) + (value(col + 1, row) - value(col, row)) * tx;
+        let top = value(col, row + 1) + (value(col + 1, row + 1) - value(col, row + 1)) * tx;
+        bottom + (top - bottom) * ty
+    }
+}
+
+/// Picks for each pixel the biome with the closest color, flipping rows so row 0 is the bottom
+fn load_image(path: &Path, config: &SimConfig) -> Result<(usize, usize, Vec<Biome>), BiomeError> {
+    let bytes = fs::read(path).map_err(BiomeError::Io)?;
+    let extension = path.extension().and_then(|e| e.to_str()).unwrap_or("png");
+    let image = Image::from_buffer(
+        &bytes,
+        ImageType::Extension(extension),
+        CompressedImageFormats::NONE,
+        true,
+        ImageSampler::Default,
+    )
+    .map_err(BiomeError::Image)?
+    .convert(TextureFormat::Rgba8UnormSrgb)
+    .ok_or(BiomeError::Format)?;
+
+    let size = image.texture_descriptor.size;
+    let (cols, rows) = (size.width as usize, size.height as usize);
+    let closest = |pixel: &[u8]| {
+        let dist = |biome: &Biome| {
+            let (r, g, b) = biome.params(config).color;
+            [r, g, b]
+                .iter()
+                .zip(pixel)
+                .map(|(c, p)| (*c as i32 - *p as i32).pow(2))
+                .sum::<i32>()
+        };
+        *Biome::ALL.iter().min_by_key(|b| dist(b)).unwrap()
+    };
+
+    let pixels: Vec<Biome> = image.data.chunks_exact(4).map(closest).collect();
+    let cells = pixels.chunks_exact(cols).rev().flatten().copied().collect();
+
+    Ok((cols, rows, cells))
+}
+
+impl fmt::Display for BiomeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BiomeError::Io(e) => write!(f, "{}", e),
+            BiomeError::Image(e) => write!(f, "failed to decode image: {}", e),
+            BiomeError::Format => write!(f, "unsupported image format"),
+        }
+    }
+}
+
+impl std::error::Error for BiomeError {}
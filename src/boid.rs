@@ -1,13 +1,25 @@
 use std::f32::consts::PI;
 use std::time::Duration;
 
-use bevy::math::vec3;
+use bevy::ecs::query::ReadOnlyWorldQuery;
+use bevy::math::{vec2, vec3};
 use bevy::prelude::*;
-use bevy::time::common_conditions::on_timer;
-use bevy::utils::Instant;
 use rand::Rng;
 
-use crate::elements::{Consumable, Food, FoodBundle, Poison, PoisonBundle};
+use crate::biome::BiomeMap;
+use crate::brain::{gene_specs, senses, Brain};
+use crate::clock::{on_sim_timer, SimClock};
+use crate::elements::{Consumable, FoodBundle, PoisonBundle};
+use crate::genome::Genome;
+use crate::lineage::{Ancestry, Lineage};
+use crate::neat::{Innovations, NeatGenome};
+use crate::obstacles::{
+    get_avoidance_force, is_blocked, is_visible, push_out_of_obstacles, Obstacle,
+};
+use crate::season::SeasonCycle;
+use crate::snapshot::{BoidSnapshot, PredatorSnapshot};
+use crate::spatial::{rebuild_agent_grid, rebuild_consumable_grid, SpatialIndex};
+use crate::species::{species_color, Species};
 use crate::utils::*;
 use crate::*;
 
@@ -18,19 +30,70 @@ pub struct Boid;
 #[derive(Component)]
 pub struct Predator;
 #[derive(Component)]
-struct Velocity(Vec2);
+pub struct Velocity(pub Vec2);
 #[derive(Component)]
 struct Acceleration(Vec2);
 #[derive(Component)]
-struct Health(f32);
+pub struct Health(pub f32);
 #[derive(Component)]
-struct ReplicateTimer(Timer);
+pub struct ReplicateTimer(pub Timer);
+/// Sim clock tick the boid was born at
 #[derive(Component)]
-pub struct BirthTimeStamp(pub Instant);
+pub struct BirthTimeStamp(pub u64);
 
+/// Drops food and poison where a boid died
 #[derive(Event)]
-struct BoidDeathFoodSpawnEvent(Vec2);
+pub struct BoidDeathFoodSpawnEvent(pub Vec2);
 
+/// Boids that aren't predators
+pub type PreyFilter = (With<Boid>, Without<Predator>);
+
+type ReplicateQuery = (
+    Entity,
+    &'static mut ReplicateTimer,
+    &'static Transform,
+    &'static Genome,
+    &'static NeatGenome,
+    &'static Lineage,
+    &'static mut Health,
+);
+
+type PredatorSteeringQuery = (
+    &'static Transform,
+    &'static Velocity,
+    &'static mut Acceleration,
+    &'static Dna,
+    &'static PredatorDna,
+    &'static Brain,
+    &'static Health,
+);
+
+type MateSeekingQuery = (
+    &'static Transform,
+    &'static Velocity,
+    &'static mut Acceleration,
+    &'static Dna,
+    &'static ReplicateTimer,
+    Has<Predator>,
+);
+
+/// Genes, lineage and position a new agent starts with
+pub struct Newborn {
+    pub pos: (f32, f32),
+    pub genome: Genome,
+    pub neat: NeatGenome,
+    pub lineage: Lineage,
+}
+
+/// What `mate` passes on to the child
+struct Mating {
+    genome: Genome,
+    neat: NeatGenome,
+    pos: Vec2,
+    parents: (Lineage, Lineage),
+}
+
+/// Traits read by the steering and movement systems, built from the genes of the same name
 #[derive(Component, Clone, Copy)]
 pub struct Dna {
     pub steering_force: f32,
@@ -55,7 +118,12 @@ pub struct BoidBundle {
     boid: Boid,
     velocity: Velocity,
     acceleration: Acceleration,
+    genome: Genome,
+    neat: NeatGenome,
     dna: Dna,
+    lineage: Lineage,
+    species: Species,
+    brain: Brain,
     health: Health,
     replicate_timer: ReplicateTimer,
     birth_ts: BirthTimeStamp,
@@ -70,20 +138,38 @@ pub struct PredatorBundle {
 
 impl Plugin for BoidPlugin {
     fn build(&self, app: &mut bevy::prelude::App) {
+        // Chained so every run with the same seed applies the same updates in the same order
         app.add_systems(
-            Update,
+            FixedUpdate,
             (
-                update_boid_transform,
+                (rebuild_agent_grid, rebuild_consumable_grid),
                 boundary_boids_direction_update,
-                (update_boid_color, update_predator_color),
                 (update_boid_direction, update_predator_direction),
+                seek_mates,
+                boid_separation,
+                update_boid_transform,
+                // Collisions see the positions after this tick's move
+                rebuild_agent_grid,
                 (handle_boid_collision, handle_predator_collision),
-                (boids_replicate, predators_replicate),
-                (camera_follow_boid, camera_follow_predator),
+                // Eaten prey and food are gone before starved boids are counted
+                apply_deferred,
+                boid_health_tick.run_if(on_sim_timer(0.5)),
                 despawn_boids,
-                boid_health_tick.run_if(on_timer(Duration::from_secs_f32(0.5))),
+                // The dead don't replicate
+                apply_deferred,
                 handle_boid_despawn_events,
-                boid_separation,
+                boids_replicate,
+                predators_replicate,
+            )
+                .chain()
+                .in_set(SimSet::Boids)
+                .run_if(in_state(SimState::Simulating)),
+        )
+        .add_systems(
+            Update,
+            (
+                (update_boid_color, update_predator_color),
+                (camera_follow_boid, camera_follow_predator),
             )
                 .run_if(in_state(SimState::Simulating)),
         )
@@ -92,77 +178,242 @@ impl Plugin for BoidPlugin {
 }
 
 fn boids_replicate(
-    time: Res<Time>,
-    mut commands: Commands,
-    handle: Res<GlobalTextureHandle>,
-    mut boid_query: Query<
-        (&mut ReplicateTimer, &Transform, &Dna, &Health),
-        (With<Boid>, Without<Predator>),
-    >,
+    spawner: Spawner,
+    index: Res<SpatialIndex>,
+    mut ancestry: ResMut<Ancestry>,
+    mut innovations: ResMut<Innovations>,
+    mut boid_query: Query<ReplicateQuery, PreyFilter>,
 ) {
-    if boid_query.iter().len() >= NUM_BOIDS {
+    let Spawner {
+        mut commands,
+        clock,
+        config,
+        mut rng,
+        handle,
+    } = spawner;
+    if boid_query.iter().len() >= config.num_boids {
         return;
     }
 
-    let mut rng = rand::thread_rng();
-    let replication_threshold = 1.0 - BOID_REPLICATE_PROBABILITY;
-    for (mut timer, transform, dna, health) in boid_query.iter_mut() {
-        if rng.gen_range(0.0..1.0) <= replication_threshold && health.0 > BOID_MAX_HEALTH / 2.0 {
+    let rng = &mut rng.0;
+    let specs = gene_specs(&config, false);
+    let replication_threshold = 1.0 - config.boid_replicate_probability;
+    let mut ready = Vec::new();
+    for (entity, mut timer, transform, genome, neat, lineage, health) in boid_query.iter_mut() {
+        if rng.gen_range(0.0..1.0) <= replication_threshold
+            && health.0 > config.boid_max_health / 2.0
+        {
             continue;
         }
         // No tick until health valid
-        if !timer.0.tick(time.delta()).just_finished() {
+        if !timer.0.tick(clock.delta()).just_finished() {
+            continue;
+        }
+        if config.reproduction != Reproduction::Asexual {
+            ready.push(entity);
             continue;
         }
         let (x, y) = (transform.translation.x, transform.translation.y);
-        let mut child_dna = dna.clone();
-        child_dna.mutate();
+        let child_genome = genome.child(&specs, &config, rng);
+        let child_neat = neat.child(genome, false, &config, &mut innovations, rng);
+        let child_lineage = ancestry.offspring(lineage, None, &child_genome, false, clock.tick);
+
+        let newborn = Newborn {
+            pos: (x, y),
+            genome: child_genome,
+            neat: child_neat,
+            lineage: child_lineage,
+        };
+        commands.spawn(BoidBundle::child(
+            newborn,
+            handle.atlas(),
+            false,
+            clock.tick,
+            &config,
+            rng,
+        ));
+    }
 
+    for parent in ready {
+        let Some(Mating {
+            genome,
+            neat,
+            pos,
+            parents,
+        }) = mate(parent, false, &config, &index, &mut boid_query, rng)
+        else {
+            continue;
+        };
+        let child_genome = genome.child(&specs, &config, rng);
+        let child_neat = neat.child(&genome, false, &config, &mut innovations, rng);
+        let child_lineage = ancestry.offspring(
+            &parents.0,
+            Some(&parents.1),
+            &child_genome,
+            false,
+            clock.tick,
+        );
+        let newborn = Newborn {
+            pos: pos.into(),
+            genome: child_genome,
+            neat: child_neat,
+            lineage: child_lineage,
+        };
         commands.spawn(BoidBundle::child(
-            (x, y),
-            &child_dna,
-            handle.0.clone().unwrap(),
+            newborn,
+            handle.atlas(),
             false,
+            clock.tick,
+            &config,
+            rng,
         ));
     }
 }
 
 fn predators_replicate(
-    time: Res<Time>,
-    mut commands: Commands,
-    handle: Res<GlobalTextureHandle>,
-    mut predators_query: Query<
-        (&mut ReplicateTimer, &Transform, &Dna, &PredatorDna, &Health),
-        With<Predator>,
-    >,
+    spawner: Spawner,
+    index: Res<SpatialIndex>,
+    mut ancestry: ResMut<Ancestry>,
+    mut innovations: ResMut<Innovations>,
+    mut predators_query: Query<ReplicateQuery, With<Predator>>,
 ) {
-    if predators_query.iter().len() >= NUM_PREDATORS {
+    let Spawner {
+        mut commands,
+        clock,
+        config,
+        mut rng,
+        handle,
+    } = spawner;
+    if predators_query.iter().len() >= config.num_predators {
         return;
     }
 
-    let mut rng = rand::thread_rng();
-    let replication_threshold = 1.0 - BOID_REPLICATE_PROBABILITY;
-    for (mut timer, transform, dna, predator_dna, health) in predators_query.iter_mut() {
-        if rng.gen_range(0.0..1.0) <= replication_threshold && health.0 > BOID_MAX_HEALTH / 3.0 {
+    let rng = &mut rng.0;
+    let specs = gene_specs(&config, true);
+    let replication_threshold = 1.0 - config.boid_replicate_probability;
+    let mut ready = Vec::new();
+    for (entity, mut timer, transform, genome, neat, lineage, health) in predators_query.iter_mut()
+    {
+        if rng.gen_range(0.0..1.0) <= replication_threshold
+            && health.0 > config.boid_max_health / 3.0
+        {
             continue;
         }
         // No tick until health valid
-        if !timer.0.tick(time.delta()).just_finished() {
+        if !timer.0.tick(clock.delta()).just_finished() {
+            continue;
+        }
+        if config.reproduction != Reproduction::Asexual {
+            ready.push(entity);
             continue;
         }
         let (x, y) = (transform.translation.x, transform.translation.y);
-        let mut child_dna = dna.clone();
-        let mut child_predator_dna = predator_dna.clone();
-        child_dna.mutate();
-        child_predator_dna.mutate();
+        let child_genome = genome.child(&specs, &config, rng);
+        let child_neat = neat.child(genome, true, &config, &mut innovations, rng);
+        let child_lineage = ancestry.offspring(lineage, None, &child_genome, true, clock.tick);
 
+        let newborn = Newborn {
+            pos: (x, y),
+            genome: child_genome,
+            neat: child_neat,
+            lineage: child_lineage,
+        };
         commands.spawn(PredatorBundle::child(
-            (x, y),
-            &child_dna,
-            &predator_dna,
-            handle.0.clone().unwrap(),
+            newborn,
+            handle.atlas(),
+            clock.tick,
+            &config,
+            rng,
         ));
     }
+
+    for parent in ready {
+        let Some(Mating {
+            genome,
+            neat,
+            pos,
+            parents,
+        }) = mate(parent, true, &config, &index, &mut predators_query, rng)
+        else {
+            continue;
+        };
+        let child_genome = genome.child(&specs, &config, rng);
+        let child_neat = neat.child(&genome, true, &config, &mut innovations, rng);
+        let child_lineage = ancestry.offspring(
+            &parents.0,
+            Some(&parents.1),
+            &child_genome,
+            true,
+            clock.tick,
+        );
+        let newborn = Newborn {
+            pos: pos.into(),
+            genome: child_genome,
+            neat: child_neat,
+            lineage: child_lineage,
+        };
+        commands.spawn(PredatorBundle::child(
+            newborn,
+            handle.atlas(),
+            clock.tick,
+            &config,
+            rng,
+        ));
+    }
+}
+
+/// Crosses the parent's genes with those of the closest agent of its kind within
+/// `mate_perception_radius` that can pay the mating cost, both parents pay it.
+/// Returns the crossed genes and networks, the parent's position and the lineages of both parents.
+/// Without a mate in reach
+/// the parent stays ready and tries again on its next timer tick.
+fn mate<F: ReadOnlyWorldQuery>(
+    parent: Entity,
+    is_predator: bool,
+    config: &SimConfig,
+    index: &SpatialIndex,
+    query: &mut Query<ReplicateQuery, F>,
+    rng: &mut impl Rng,
+) -> Option<Mating> {
+    let Reproduction::Sexual { crossover } = config.reproduction else {
+        return None;
+    };
+    let can_mate = |e: Entity| query.get(e).is_ok_and(|(.., h)| h.0 > config.mating_cost);
+    let (_, _, transform, genome, neat, lineage, _) = query.get(parent).ok()?;
+    let pos = transform.translation.truncate();
+    let radius = config.mate_perception_radius;
+    // Ties go to the first inserted, so the pick doesn't depend on the grid layout
+    let mate = index
+        .agents
+        .candidates(pos, radius)
+        .filter(|(_, item, _)| item.tag == is_predator && item.entity != parent)
+        .map(|(i, item, item_pos)| (i, item.entity, item_pos.distance_squared(pos)))
+        .filter(|(_, e, dist)| *dist <= radius * radius && can_mate(*e))
+        .min_by(|a, b| a.2.total_cmp(&b.2).then(a.0.cmp(&b.0)))
+        .map(|(_, mate, _)| mate)
+        .filter(|_| can_mate(parent));
+    let Some(mate) = mate else {
+        if let Ok((_, mut timer, ..)) = query.get_mut(parent) {
+            let duration = timer.0.duration();
+            timer.0.set_elapsed(duration);
+        }
+        return None;
+    };
+
+    let (_, _, _, mate_genome, mate_neat, mate_lineage, _) = query.get(mate).ok()?;
+    let mating = Mating {
+        genome: genome.crossover(mate_genome, crossover, rng),
+        neat: neat.crossover(mate_neat, crossover, rng),
+        pos,
+        parents: (*lineage, *mate_lineage),
+    };
+    for e in [parent, mate] {
+        if let Ok((.., mut health)) = query.get_mut(e) {
+            health.0 -= config.mating_cost;
+        }
+    }
+
+    Some(mating)
 }
 
 fn camera_follow_predator(
@@ -180,7 +431,7 @@ fn camera_follow_predator(
     let mut cam_transform = cam_query.single_mut();
     if settings.camera_follow_predator {
         if let Some(transform) = predator_query.iter().next() {
-            let mut new_pos = transform.translation.clone();
+            let mut new_pos = transform.translation;
             new_pos.y -= 50.0;
             cam_transform.translation = cam_transform.translation.lerp(new_pos, 0.05);
         }
@@ -188,7 +439,7 @@ fn camera_follow_predator(
 }
 
 fn camera_follow_boid(
-    boid_query: Query<&Transform, (With<Boid>, Without<Predator>)>,
+    boid_query: Query<&Transform, PreyFilter>,
     settings: Res<Settings>,
     mut cam_query: Query<&mut Transform, (With<Camera>, Without<Boid>)>,
 ) {
@@ -202,7 +453,7 @@ fn camera_follow_boid(
     let mut cam_transform = cam_query.single_mut();
     if settings.camera_follow_boid {
         if let Some(transform) = boid_query.iter().next() {
-            let mut new_pos = transform.translation.clone();
+            let mut new_pos = transform.translation;
             new_pos.y -= 50.0;
             cam_transform.translation = cam_transform.translation.lerp(new_pos, 0.05);
         }
@@ -210,7 +461,11 @@ fn camera_follow_boid(
 }
 
 fn update_boid_transform(
+    clock: Res<SimClock>,
+    config: Res<SimConfig>,
+    biomes: Res<BiomeMap>,
     mut boids_query: Query<(&mut Transform, &mut Velocity, &mut Acceleration, &Dna), With<Boid>>,
+    obstacles_query: Query<&Obstacle>,
 ) {
     if boids_query.is_empty() {
         return;
@@ -226,23 +481,47 @@ fn update_boid_transform(
         velocity.0 = (velocity.0 + acceleration.0).normalize();
         acceleration.0 = Vec2::ZERO;
 
-        let new_translation =
-            transform.translation + vec3(velocity.0.x, velocity.0.y, 0.0) * dna.speed;
-        if !new_translation.is_nan() {
-            transform.translation = new_translation;
+        let terrain_speed = biomes.params(old_pos.truncate(), &config).speed;
+        let new_translation = transform.translation
+            + vec3(velocity.0.x, velocity.0.y, 0.0)
+                * dna.speed
+                * terrain_speed
+                * clock.delta_secs();
+        if new_translation.is_nan() {
+            transform.rotation = Quat::from_rotation_z(
+                calc_rotation_angle(old_pos, transform.translation) + PI / 2.0,
+            );
+            continue;
         }
-
+        // Face the direction moved in, before any wrap or bounce displaces the boid
         transform.rotation =
-            Quat::from_rotation_z(calc_rotation_angle(old_pos, transform.translation) + PI / 2.0);
+            Quat::from_rotation_z(calc_rotation_angle(old_pos, new_translation) + PI / 2.0);
+
+        let mut pos = new_translation.truncate();
+        match config.topology {
+            Topology::Walls => reflect_off_walls(&mut pos, &mut velocity.0, &config),
+            Topology::Torus => pos = limit_to_world(pos.into(), &config).into(),
+            Topology::Soft => {}
+        }
+        push_out_of_obstacles(&mut pos, &mut velocity.0, &obstacles_query);
+        transform.translation = pos.extend(new_translation.z);
     }
 }
 
 fn handle_boid_despawn_events(
-    mut commands: Commands,
-    handle: Res<GlobalTextureHandle>,
+    spawner: Spawner,
     mut events: EventReader<BoidDeathFoodSpawnEvent>,
+    biomes: Res<BiomeMap>,
+    obstacles_query: Query<&Obstacle>,
 ) {
-    let mut rng = rand::thread_rng();
+    let Spawner {
+        mut commands,
+        clock,
+        config,
+        mut rng,
+        handle,
+    } = spawner;
+    let rng = &mut rng.0;
     for e in events.read() {
         for _ in 0..rng.gen_range(2..5) {
             let (x, y) = (e.0.x, e.0.y);
@@ -251,11 +530,26 @@ fn handle_boid_despawn_events(
                 y + rng.gen_range(-30.0..30.0),
             );
 
-            let (x, y) = limit_to_world((x, y));
-            if rng.gen_range(0.0..1.0) > 0.85 {
-                commands.spawn(PoisonBundle::new((x, y), handle.0.clone().unwrap()));
+            let (x, y) = limit_to_world((x, y), &config);
+            let is_poison = rng.gen_range(0.0..1.0) > 0.85;
+            if is_blocked(vec2(x, y), &obstacles_query) {
+                continue;
+            }
+            if is_poison {
+                commands.spawn(PoisonBundle::new(
+                    (x, y),
+                    handle.atlas(),
+                    clock.tick,
+                    &config,
+                ));
             } else {
-                commands.spawn(FoodBundle::new((x, y), handle.0.clone().unwrap()));
+                commands.spawn(FoodBundle::new(
+                    (x, y),
+                    handle.atlas(),
+                    clock.tick,
+                    &config,
+                    &biomes,
+                ));
             }
         }
     }
@@ -263,60 +557,62 @@ fn handle_boid_despawn_events(
 
 fn handle_boid_collision(
     mut commands: Commands,
-    mut boids_query: Query<(&mut Health, &Transform), (With<Boid>, Without<Predator>)>,
-    consumable_query: Query<(&Transform, &Consumable, Entity), With<Consumable>>,
+    config: Res<SimConfig>,
+    mut index: ResMut<SpatialIndex>,
+    mut boids_query: Query<&mut Health, PreyFilter>,
+    consumable_query: Query<&Consumable>,
 ) {
-    let mut consumables = Vec::new();
-    for (t, c, e) in consumable_query.iter() {
-        consumables.push((t.translation, c.0, e));
-    }
-
-    for (mut health, boid_transform) in boids_query.iter_mut() {
-        let mut food_idx = None;
-        for (index, (t, c, e)) in consumables.iter().enumerate() {
-            if boid_transform.translation.distance_squared(*t)
-                < BOID_COLLISION_RADIUS * BOID_COLLISION_RADIUS
-            {
-                health.0 = (health.0 + c).min(BOID_MAX_HEALTH);
-                commands.entity(*e).despawn();
-                food_idx = Some(index);
-                break;
-            }
-        }
-        if let Some(index) = food_idx {
-            consumables.remove(index);
+    // Boids eaten this tick still get their bite, despawns only apply after this system
+    let (boids, positions): (Vec<Entity>, Vec<Vec2>) = index
+        .agents
+        .items()
+        .iter()
+        .filter(|item| !item.tag)
+        .map(|item| (item.entity, item.pos))
+        .unzip();
+    let claims =
+        index
+            .consumables
+            .claim_first_within(&positions, config.boid_collision_radius, |_| true);
+
+    for (boid, claim) in boids.into_iter().zip(claims) {
+        let (Some(claim), Ok(mut health)) = (claim, boids_query.get_mut(boid)) else {
+            continue;
+        };
+        if let Ok(c) = consumable_query.get(claim.entity) {
+            health.0 = (health.0 + c.0).min(config.boid_max_health);
         }
+        commands.entity(claim.entity).despawn();
     }
 }
 
 fn handle_predator_collision(
     mut commands: Commands,
+    config: Res<SimConfig>,
+    mut index: ResMut<SpatialIndex>,
     mut writer: EventWriter<BoidDeathFoodSpawnEvent>,
-    mut predator_query: Query<(&mut Health, &Transform), With<Predator>>,
-    boids_query: Query<(&Transform, Entity), (With<Boid>, Without<Predator>)>,
+    mut predator_query: Query<&mut Health, With<Predator>>,
 ) {
-    let mut boids = Vec::new();
-    for (t, e) in boids_query.iter() {
-        boids.push((t.translation, e));
-    }
-
-    for (mut health, predator_transform) in predator_query.iter_mut() {
-        let mut boid_idx = None;
-        for (index, (t, e)) in boids.iter().enumerate() {
-            if predator_transform.translation.distance_squared(*t)
-                < PREDATOR_COLLISION_RADIUS * PREDATOR_COLLISION_RADIUS
-            {
-                health.0 = (health.0 + BOID_NUTRITION).min(BOID_MAX_HEALTH);
-                writer.send(BoidDeathFoodSpawnEvent(t.truncate()));
-                boid_idx = Some(index);
-                commands.entity(*e).despawn();
-                break;
-            }
-        }
+    let (predators, positions): (Vec<Entity>, Vec<Vec2>) = index
+        .agents
+        .items()
+        .iter()
+        .filter(|item| item.tag)
+        .map(|item| (item.entity, item.pos))
+        .unzip();
+    let claims = index.agents.claim_first_within(
+        &positions,
+        config.predator_collision_radius,
+        |is_predator| !is_predator,
+    );
 
-        if let Some(index) = boid_idx {
-            boids.remove(index);
-        }
+    for (predator, claim) in predators.into_iter().zip(claims) {
+        let (Some(claim), Ok(mut health)) = (claim, predator_query.get_mut(predator)) else {
+            continue;
+        };
+        health.0 = (health.0 + config.boid_nutrition).min(config.boid_max_health);
+        writer.send(BoidDeathFoodSpawnEvent(claim.pos));
+        commands.entity(claim.entity).despawn();
     }
 }
 
@@ -333,23 +629,35 @@ fn despawn_boids(
     }
 }
 
-fn boid_health_tick(mut boids_query: Query<(&mut Health, &Dna), With<Boid>>) {
-    for (mut health, dna) in boids_query.iter_mut() {
-        health.0 -= BOID_TICK_DAMAGE * dna.speed.max(1.0) * 0.5;
+fn boid_health_tick(
+    config: Res<SimConfig>,
+    biomes: Res<BiomeMap>,
+    mut boids_query: Query<(&mut Health, &Transform, &Dna), With<Boid>>,
+) {
+    for (mut health, transform, dna) in boids_query.iter_mut() {
+        let energy_cost = biomes
+            .params(transform.translation.truncate(), &config)
+            .energy_cost;
+        health.0 -= config.boid_tick_damage
+            * (dna.speed / config.boid_base_speed).max(1.0)
+            * 0.5
+            * energy_cost;
     }
 }
 
 fn boundary_boids_direction_update(
+    config: Res<SimConfig>,
     mut boids_query: Query<(&Transform, &Velocity, &mut Acceleration), With<Boid>>,
 ) {
-    if boids_query.is_empty() {
+    // Walls and torus keep boids inside on their own while moving
+    if boids_query.is_empty() || config.topology != Topology::Soft {
         return;
     }
 
     for (transform, velocity, mut acceleration) in boids_query.iter_mut() {
         // Boundary collisions
         let (x, y) = (transform.translation.x, transform.translation.y);
-        let (min_x, min_y, max_x, max_y) = get_world_bounds();
+        let (min_x, min_y, max_x, max_y) = get_world_bounds(&config);
         if x <= min_x || x >= max_x || y <= min_y || y >= max_y {
             // Don't randomize direction here, boid will spin around
             acceleration.0 +=
@@ -360,167 +668,299 @@ fn boundary_boids_direction_update(
 }
 
 fn update_predator_direction(
-    mut predator_query: Query<
-        (&Transform, &Velocity, &mut Acceleration, &Dna, &PredatorDna),
-        With<Predator>,
-    >,
-    boids_query: Query<&Transform, (With<Boid>, Without<Predator>)>,
+    mut predator_query: Query<PredatorSteeringQuery, With<Predator>>,
+    config: Res<SimConfig>,
+    cycle: Res<SeasonCycle>,
+    index: Res<SpatialIndex>,
+    obstacles_query: Query<&Obstacle>,
 ) {
-    if predator_query.is_empty() || boids_query.is_empty() {
+    if predator_query.is_empty() {
         return;
     }
 
-    for (predator_transform, velocity, mut acceleration, dna, predator_dna) in
-        predator_query.iter_mut()
-    {
-        let mut closest_boid_dist = f32::INFINITY;
-        let mut closest_boid_pos = None;
-        for boid_transform in boids_query.iter() {
-            let dist = predator_transform
-                .translation
-                .distance_squared(boid_transform.translation);
-            if dist <= closest_boid_dist
-                && dist <= predator_dna.prey_perception * predator_dna.prey_perception
-            {
-                closest_boid_dist = dist;
-                closest_boid_pos = Some(boid_transform.translation.truncate());
+    let perception = cycle.perception(&config);
+    // Each predator only writes its own acceleration, the index is read-only
+    predator_query.par_iter_mut().for_each(
+        |(predator_transform, velocity, mut acceleration, dna, predator_dna, brain, health)| {
+            let pos = predator_transform.translation.truncate();
+            let prey_radius = predator_dna.prey_perception * perception;
+            let closest_boid_pos =
+                index
+                    .agents
+                    .closest(pos, prey_radius, |is_predator, boid_pos| {
+                        !is_predator && is_visible(pos, boid_pos, &obstacles_query)
+                    });
+
+            match config.controller {
+                Controller::Pull => {
+                    if let Some(boid_pos) = closest_boid_pos {
+                        acceleration.0 += get_steering_force(
+                            boid_pos,
+                            predator_transform.translation.truncate(),
+                            velocity.0,
+                        ) * dna.steering_force.abs()
+                            * predator_dna.prey_pull;
+                    }
+                }
+                Controller::Network { .. } | Controller::Neat { .. } => {
+                    let inputs = senses(
+                        pos,
+                        &[(closest_boid_pos, prey_radius)],
+                        health.0,
+                        velocity.0,
+                        &config,
+                    );
+                    acceleration.0 +=
+                        brain.steer(&inputs) * BRAIN_OUTPUT_SCALE * dna.steering_force.abs();
+                }
             }
-        }
+            if !obstacles_query.is_empty() {
+                acceleration.0 += get_avoidance_force(
+                    pos,
+                    velocity.0,
+                    config.obstacle_avoid_distance,
+                    &obstacles_query,
+                ) * dna.steering_force.abs()
+                    * config.obstacle_avoid_force;
+            }
+        },
+    );
+}
 
-        if let Some(boid_pos) = closest_boid_pos {
-            acceleration.0 += get_steering_force(
-                boid_pos,
-                predator_transform.translation.truncate(),
-                velocity.0,
-            ) * dna.steering_force.abs()
-                * predator_dna.prey_pull;
-        }
+/// Agents that are ready to replicate sexually but haven't found a mate
+/// steer towards the closest one of their kind they can see
+fn seek_mates(
+    mut agents_query: Query<MateSeekingQuery, With<Boid>>,
+    config: Res<SimConfig>,
+    cycle: Res<SeasonCycle>,
+    index: Res<SpatialIndex>,
+    obstacles_query: Query<&Obstacle>,
+) {
+    if config.reproduction == Reproduction::Asexual {
+        return;
     }
+
+    let radius = config.mate_perception_radius * cycle.perception(&config);
+    agents_query.par_iter_mut().for_each(
+        |(transform, velocity, mut acceleration, dna, timer, is_predator)| {
+            // `mate` holds the timer at its end until a mate is found
+            if timer.0.elapsed() < timer.0.duration() {
+                return;
+            }
+            let pos = transform.translation.truncate();
+            let closest_mate = index.agents.closest(pos, radius, |tag, mate_pos| {
+                tag == is_predator && mate_pos != pos && is_visible(pos, mate_pos, &obstacles_query)
+            });
+            if let Some(mate) = closest_mate {
+                acceleration.0 +=
+                    get_steering_force(mate, pos, velocity.0) * dna.steering_force.abs();
+            }
+        },
+    );
 }
 
-fn boid_separation(mut boids_query: Query<(&Transform, &Velocity, &mut Acceleration), With<Boid>>) {
+fn boid_separation(
+    index: Res<SpatialIndex>,
+    mut boids_query: Query<(&Transform, &Velocity, &mut Acceleration), With<Boid>>,
+) {
     if boids_query.is_empty() {
         return;
     }
 
-    let all_positions: Vec<Vec2> = boids_query
-        .into_iter()
-        .map(|(t, _, _)| t.translation.truncate())
-        .collect();
+    boids_query
+        .par_iter_mut()
+        .for_each(|(transform, velocity, mut acceleration)| {
+            let boid_pos = transform.translation.truncate();
+            let mut neighbours: Vec<(usize, Vec2)> = index
+                .agents
+                .candidates(boid_pos, BOID_SEPARATION_RADIUS)
+                .map(|(i, _, neighbour_pos)| (i, neighbour_pos))
+                .collect();
+            // Add up the forces in query order, float sums depend on it
+            neighbours.sort_unstable_by_key(|(i, _)| *i);
 
-    for (transform, velocity, mut acceleration) in boids_query.iter_mut() {
-        let boid_pos = transform.translation.truncate();
-        for neighbour_pos in all_positions.iter() {
-            let dist = neighbour_pos.distance_squared(boid_pos);
-            if dist != 0.0 && dist <= 20.0 {
-                acceleration.0 += get_steering_force(*neighbour_pos, boid_pos, velocity.0) * -0.01;
+            for (_, neighbour_pos) in neighbours.iter() {
+                let dist = neighbour_pos.distance_squared(boid_pos);
+                if dist != 0.0 && dist <= BOID_SEPARATION_RADIUS * BOID_SEPARATION_RADIUS {
+                    acceleration.0 +=
+                        get_steering_force(*neighbour_pos, boid_pos, velocity.0) * -0.01;
+                }
             }
-        }
-    }
+        });
 }
 
 fn update_boid_direction(
     mut boids_query: Query<
-        (&Transform, &Velocity, &mut Acceleration, &Dna),
-        (With<Boid>, Without<Predator>),
+        (
+            &Transform,
+            &Velocity,
+            &mut Acceleration,
+            &Dna,
+            &Brain,
+            &Health,
+        ),
+        PreyFilter,
     >,
-    predator_query: Query<&Transform, With<Predator>>,
-    food_query: Query<&Transform, With<Food>>,
-    poison_query: Query<&Transform, With<Poison>>,
+    config: Res<SimConfig>,
+    cycle: Res<SeasonCycle>,
+    index: Res<SpatialIndex>,
+    obstacles_query: Query<&Obstacle>,
 ) {
     if boids_query.is_empty() {
         return;
     }
 
-    for (transform, velocity, mut acceleration, dna) in boids_query.iter_mut() {
-        let closest_food = get_closest_item(
-            transform.translation,
-            dna.food_perception_radius,
-            food_query.iter(),
-        );
-        let closest_poison = get_closest_item(
-            transform.translation,
-            dna.poison_perception_radius,
-            poison_query.iter(),
-        );
-        let closest_predator = get_closest_item(
-            transform.translation,
-            dna.predator_perception_radius,
-            predator_query.iter(),
-        );
+    let perception = cycle.perception(&config);
+    boids_query.par_iter_mut().for_each(
+        |(transform, velocity, mut acceleration, dna, brain, health)| {
+            let pos = transform.translation.truncate();
+            // Obstacles hide whatever is behind them
+            let is_seen = |item_pos| is_visible(pos, item_pos, &obstacles_query);
+            let food_radius = dna.food_perception_radius * perception;
+            let poison_radius = dna.poison_perception_radius * perception;
+            let predator_radius = dna.predator_perception_radius * perception;
+            let closest_food = index
+                .consumables
+                .closest(pos, food_radius, |is_food, item_pos| {
+                    is_food && is_seen(item_pos)
+                });
+            let closest_poison =
+                index
+                    .consumables
+                    .closest(pos, poison_radius, |is_food, item_pos| {
+                        !is_food && is_seen(item_pos)
+                    });
+            let closest_predator =
+                index
+                    .agents
+                    .closest(pos, predator_radius, |is_predator, item_pos| {
+                        is_predator && is_seen(item_pos)
+                    });
 
-        if let Some(food) = closest_food {
-            acceleration.0 +=
-                get_steering_force(food, transform.translation.truncate(), velocity.0)
-                    * dna.steering_force.abs()
-                    * dna.food_pull;
-        }
-        if let Some(poison) = closest_poison {
-            acceleration.0 +=
-                get_steering_force(poison, transform.translation.truncate(), velocity.0)
-                    * dna.steering_force.abs()
-                    * dna.poison_pull;
-        }
-        if let Some(predator) = closest_predator {
-            acceleration.0 +=
-                get_steering_force(predator, transform.translation.truncate(), velocity.0)
-                    * dna.steering_force.abs()
-                    * dna.predator_pull;
-        }
-    }
-}
-
-fn get_closest_item<'a, I>(origin: Vec3, range: f32, items: I) -> Option<Vec2>
-where
-    I: IntoIterator<Item = &'a Transform>,
-{
-    let mut closest_item_dist = f32::INFINITY;
-    let mut closest_item_pos = None;
-
-    for t in items {
-        let dist = t.translation.distance_squared(origin);
-        if dist <= closest_item_dist && dist <= range * range {
-            closest_item_dist = dist;
-            closest_item_pos = Some(t.translation.truncate());
-        }
-    }
-
-    closest_item_pos
+            match config.controller {
+                Controller::Pull => {
+                    if let Some(food) = closest_food {
+                        acceleration.0 +=
+                            get_steering_force(food, transform.translation.truncate(), velocity.0)
+                                * dna.steering_force.abs()
+                                * dna.food_pull;
+                    }
+                    if let Some(poison) = closest_poison {
+                        acceleration.0 += get_steering_force(
+                            poison,
+                            transform.translation.truncate(),
+                            velocity.0,
+                        ) * dna.steering_force.abs()
+                            * dna.poison_pull;
+                    }
+                    if let Some(predator) = closest_predator {
+                        acceleration.0 += get_steering_force(
+                            predator,
+                            transform.translation.truncate(),
+                            velocity.0,
+                        ) * dna.steering_force.abs()
+                            * dna.predator_pull;
+                    }
+                }
+                Controller::Network { .. } | Controller::Neat { .. } => {
+                    let inputs = senses(
+                        pos,
+                        &[
+                            (closest_food, food_radius),
+                            (closest_poison, poison_radius),
+                            (closest_predator, predator_radius),
+                        ],
+                        health.0,
+                        velocity.0,
+                        &config,
+                    );
+                    acceleration.0 +=
+                        brain.steer(&inputs) * BRAIN_OUTPUT_SCALE * dna.steering_force.abs();
+                }
+            }
+            if !obstacles_query.is_empty() {
+                acceleration.0 += get_avoidance_force(
+                    pos,
+                    velocity.0,
+                    config.obstacle_avoid_distance,
+                    &obstacles_query,
+                ) * dna.steering_force.abs()
+                    * config.obstacle_avoid_force;
+            }
+        },
+    );
 }
 
 fn update_boid_color(
-    mut boids_query: Query<(&mut TextureAtlasSprite, &Health), (With<Boid>, Without<Predator>)>,
+    config: Res<SimConfig>,
+    mut boids_query: Query<(&mut TextureAtlasSprite, &Health, &Species), PreyFilter>,
 ) {
-    for (mut sprite, health) in boids_query.iter_mut() {
+    for (mut sprite, health, species) in boids_query.iter_mut() {
+        let color = match species.0 {
+            Some(id) if config.color_by_species => species_color(id),
+            _ => get_color(config.color_boid),
+        };
         sprite.color = Color::from(
-            Vec4::from(get_color(COLOR_BOID_LOW_HEALTH))
-                .lerp(Vec4::from(get_color(COLOR_BOID)), health.0 / 100.0),
+            Vec4::from(get_color(config.color_boid_low_health))
+                .lerp(Vec4::from(color), health.0 / config.boid_max_health),
         );
     }
 }
 
 fn update_predator_color(
-    mut predator_query: Query<(&mut TextureAtlasSprite, &Health), With<Predator>>,
+    config: Res<SimConfig>,
+    mut predator_query: Query<(&mut TextureAtlasSprite, &Health, &Species), With<Predator>>,
 ) {
-    for (mut sprite, health) in predator_query.iter_mut() {
+    for (mut sprite, health, species) in predator_query.iter_mut() {
+        let color = match species.0 {
+            Some(id) if config.color_by_species => species_color(id),
+            _ => get_color(config.color_predator),
+        };
         sprite.color = Color::from(
-            Vec4::from(get_color(COLOR_PREDATOR_LOW_HEALTH))
-                .lerp(Vec4::from(get_color(COLOR_PREDATOR)), health.0 / 100.0),
+            Vec4::from(get_color(config.color_predator_low_health))
+                .lerp(Vec4::from(color), health.0 / config.boid_max_health),
         );
     }
 }
 
 impl BoidBundle {
-    pub fn new(handle: Handle<TextureAtlas>, is_predator: bool) -> Self {
-        let mut rng = rand::thread_rng();
-        let x = rng.gen_range(-WORLD_W..WORLD_W);
-        let y = rng.gen_range(-WORLD_H..WORLD_H);
+    /// Founder with random genes at `pos`
+    pub fn new(
+        ancestry: &mut Ancestry,
+        pos: (f32, f32),
+        handle: Handle<TextureAtlas>,
+        is_predator: bool,
+        birth_tick: u64,
+        config: &SimConfig,
+        rng: &mut impl Rng,
+    ) -> Self {
+        let genome = Genome::rand(&gene_specs(config, is_predator), rng);
+        let neat = NeatGenome::initial(config, is_predator, rng);
+        let lineage = ancestry.founder(&genome, is_predator, birth_tick);
 
-        Self::child((x, y), &Dna::rand(), handle, is_predator)
+        let newborn = Newborn {
+            pos,
+            genome,
+            neat,
+            lineage,
+        };
+        Self::child(newborn, handle, is_predator, birth_tick, config, rng)
     }
 
-    fn child(pos: (f32, f32), dna: &Dna, handle: Handle<TextureAtlas>, is_predator: bool) -> Self {
-        let (x, y) = pos;
+    pub fn child(
+        newborn: Newborn,
+        handle: Handle<TextureAtlas>,
+        is_predator: bool,
+        birth_tick: u64,
+        config: &SimConfig,
+        rng: &mut impl Rng,
+    ) -> Self {
+        let Newborn {
+            pos: (x, y),
+            genome,
+            neat,
+            lineage,
+        } = newborn;
         let sprite_index = if is_predator { 2 } else { 0 };
         Self {
             sprite_sheet_bundle: SpriteSheetBundle {
@@ -531,92 +971,125 @@ impl BoidBundle {
                 ..default()
             },
             boid: Boid,
-            velocity: Velocity(get_rand_unit_vec2()),
+            velocity: Velocity(get_rand_unit_vec2(rng)),
             acceleration: Acceleration(Vec2::ZERO),
-            dna: dna.clone(),
-            health: Health(BOID_MAX_HEALTH),
+            dna: Dna::from_genome(&genome),
+            brain: Brain::from_genome(&genome, &neat, config, is_predator),
+            genome,
+            neat,
+            lineage,
+            species: Species::default(),
+            health: Health(config.boid_max_health),
             replicate_timer: ReplicateTimer(Timer::new(
-                Duration::from_secs_f32(BOID_REPLICATE_INTERVAL),
+                Duration::from_secs_f32(config.boid_replicate_interval),
                 TimerMode::Repeating,
             )),
-            birth_ts: BirthTimeStamp(Instant::now()),
+            birth_ts: BirthTimeStamp(birth_tick),
         }
     }
-}
 
-impl PredatorBundle {
-    pub fn new(handle: Handle<TextureAtlas>) -> Self {
+    pub fn restore(
+        snapshot: &BoidSnapshot,
+        handle: Handle<TextureAtlas>,
+        is_predator: bool,
+        config: &SimConfig,
+    ) -> Self {
+        let (x, y) = snapshot.pos;
+        let sprite_index = if is_predator { 2 } else { 0 };
+        let mut replicate_timer = Timer::new(
+            Duration::from_secs_f32(config.boid_replicate_interval),
+            TimerMode::Repeating,
+        );
+        replicate_timer.set_elapsed(snapshot.replicate_elapsed);
+
         Self {
-            boid_bundle: BoidBundle::new(handle, true),
-            predator: Predator,
-            predator_dna: PredatorDna::rand(),
+            sprite_sheet_bundle: SpriteSheetBundle {
+                texture_atlas: handle,
+                sprite: TextureAtlasSprite::new(sprite_index),
+                transform: Transform::from_scale(Vec3::splat(BOID_SPRITE_SCALE))
+                    .with_translation(vec3(x, y, 2.0)),
+                ..default()
+            },
+            boid: Boid,
+            velocity: Velocity(Vec2::new(snapshot.velocity.0, snapshot.velocity.1)),
+            acceleration: Acceleration(Vec2::ZERO),
+            dna: Dna::from_genome(&snapshot.genome),
+            brain: Brain::from_genome(&snapshot.genome, &snapshot.neat, config, is_predator),
+            genome: snapshot.genome.clone(),
+            neat: snapshot.neat.clone(),
+            lineage: snapshot.lineage,
+            species: Species(snapshot.species),
+            health: Health(snapshot.health),
+            replicate_timer: ReplicateTimer(replicate_timer),
+            birth_ts: BirthTimeStamp(snapshot.birth_tick),
         }
     }
+}
 
-    fn child(
+impl PredatorBundle {
+    pub fn new(
+        ancestry: &mut Ancestry,
         pos: (f32, f32),
-        dna: &Dna,
-        predator_dna: &PredatorDna,
         handle: Handle<TextureAtlas>,
+        birth_tick: u64,
+        config: &SimConfig,
+        rng: &mut impl Rng,
     ) -> Self {
+        let boid_bundle = BoidBundle::new(ancestry, pos, handle, true, birth_tick, config, rng);
         Self {
-            boid_bundle: BoidBundle::child(pos, dna, handle, true),
+            predator_dna: PredatorDna::from_genome(&boid_bundle.genome),
+            boid_bundle,
             predator: Predator,
-            predator_dna: predator_dna.clone(),
         }
     }
-}
 
-impl Dna {
-    fn rand() -> Self {
-        let mut rng = rand::thread_rng();
+    pub fn child(
+        newborn: Newborn,
+        handle: Handle<TextureAtlas>,
+        birth_tick: u64,
+        config: &SimConfig,
+        rng: &mut impl Rng,
+    ) -> Self {
         Self {
-            steering_force: rng.gen_range(0.0005..0.005),
-            speed: rng.gen_range(0.5..1.5),
-            food_pull: rng.gen_range(-1.5..1.5),
-            poison_pull: rng.gen_range(-1.5..1.5),
-            predator_pull: rng.gen_range(-1.5..1.5),
-            food_perception_radius: rng.gen_range(50.0..120.0),
-            poison_perception_radius: rng.gen_range(50.0..120.0),
-            predator_perception_radius: rng.gen_range(50.0..120.0),
+            predator_dna: PredatorDna::from_genome(&newborn.genome),
+            boid_bundle: BoidBundle::child(newborn, handle, true, birth_tick, config, rng),
+            predator: Predator,
         }
     }
 
-    fn get_rand_mutation_delta() -> f32 {
-        let mut rng = rand::thread_rng();
-        if rng.gen_range(0.0..1.0) > BOID_MUTATION_THRESHOLD {
-            if rng.gen_range(0.0..1.0) > 0.5 {
-                0.1
-            } else {
-                -0.1
-            }
-        } else {
-            0.0
+    pub fn restore(
+        snapshot: &PredatorSnapshot,
+        handle: Handle<TextureAtlas>,
+        config: &SimConfig,
+    ) -> Self {
+        Self {
+            boid_bundle: BoidBundle::restore(&snapshot.boid, handle, true, config),
+            predator: Predator,
+            predator_dna: PredatorDna::from_genome(&snapshot.boid.genome),
         }
     }
+}
 
-    fn mutate(&mut self) {
-        self.steering_force += Self::get_rand_mutation_delta() * 0.01;
-        self.speed += Self::get_rand_mutation_delta();
-        self.food_pull += Self::get_rand_mutation_delta();
-        self.poison_pull += Self::get_rand_mutation_delta();
-        self.predator_pull += Self::get_rand_mutation_delta();
-        self.food_perception_radius += Self::get_rand_mutation_delta() * 50.0;
-        self.poison_perception_radius += Self::get_rand_mutation_delta() * 50.0;
+impl Dna {
+    pub fn from_genome(genome: &Genome) -> Self {
+        Self {
+            steering_force: genome.get("steering_force"),
+            speed: genome.get("speed"),
+            food_pull: genome.get("food_pull"),
+            poison_pull: genome.get("poison_pull"),
+            predator_pull: genome.get("predator_pull"),
+            food_perception_radius: genome.get("food_perception_radius"),
+            poison_perception_radius: genome.get("poison_perception_radius"),
+            predator_perception_radius: genome.get("predator_perception_radius"),
+        }
     }
 }
 
 impl PredatorDna {
-    fn rand() -> Self {
-        let mut rng = rand::thread_rng();
+    pub fn from_genome(genome: &Genome) -> Self {
         Self {
-            prey_perception: rng.gen_range(30.0..100.0),
-            prey_pull: rng.gen_range(-1.0..1.0),
+            prey_perception: genome.get("prey_perception"),
+            prey_pull: genome.get("prey_pull"),
         }
     }
-
-    fn mutate(&mut self) {
-        self.prey_perception += Dna::get_rand_mutation_delta() * 50.0;
-        self.prey_pull += Dna::get_rand_mutation_delta();
-    }
 }
@@ -0,0 +1,260 @@
+use std::borrow::Cow;
+
+use bevy::prelude::*;
+
+use crate::genome::Genome;
+use crate::neat::NeatGenome;
+use crate::*;
+
+/// Items a boid senses, closest food, poison and predator
+pub const BOID_TARGETS: usize = 3;
+/// Items a predator senses, closest prey
+pub const PREDATOR_TARGETS: usize = 1;
+/// Offset and closeness of a target
+const INPUTS_PER_TARGET: usize = 3;
+/// Own health and velocity
+const SELF_INPUTS: usize = 3;
+/// Steering along x and y
+pub const OUTPUTS: usize = 2;
+
+/// Network that steers the agent, built at birth from its genes
+#[derive(Component, Debug, Clone, Default)]
+pub enum Brain {
+    /// `Pull` controller, steering comes from the pull genes
+    #[default]
+    None,
+    /// Feed-forward network with one tanh hidden layer, read from the `brain_<index>` genes
+    Layered { hidden: usize, weights: Vec<f32> },
+    /// Network grown by the `Neat` controller, see `NeatGenome`
+    Graph(Graph),
+}
+
+/// Acyclic network compiled from a `NeatGenome`, nodes are referred to by index
+#[derive(Debug, Clone, Default)]
+pub struct Graph {
+    pub num_nodes: usize,
+    /// The inputs are the nodes before it
+    pub bias: usize,
+    /// Hidden and output nodes in evaluation order, with the node and weight of each enabled
+    /// connection into them
+    pub steps: Vec<(usize, Vec<(usize, f32)>)>,
+    pub outputs: Vec<usize>,
+    pub num_hidden: usize,
+}
+
+impl Brain {
+    pub fn from_genome(
+        genome: &Genome,
+        neat: &NeatGenome,
+        config: &SimConfig,
+        is_predator: bool,
+    ) -> Self {
+        match config.controller {
+            Controller::Pull => Self::None,
+            Controller::Network { hidden } => Self::Layered {
+                hidden,
+                weights: (0..num_weights(hidden, is_predator))
+                    .map(|i| genome.get(&weight_gene_name(i)))
+                    .collect(),
+            },
+            Controller::Neat { .. } => Self::Graph(neat.graph()),
+        }
+    }
+
+    /// Steering for what the agent senses, see `senses` for the inputs
+    pub fn steer(&self, inputs: &[f32]) -> Vec2 {
+        match self {
+            Self::None => Vec2::ZERO,
+            Self::Layered { hidden, weights } => steer_layered(*hidden, weights, inputs),
+            Self::Graph(graph) => graph.steer(inputs),
+        }
+    }
+
+    /// Hidden neurons and connections, 0 for both without a network
+    pub fn complexity(&self) -> (usize, usize) {
+        match self {
+            Self::None => (0, 0),
+            Self::Layered { hidden, weights } => (*hidden, weights.len()),
+            Self::Graph(graph) => (
+                graph.num_hidden,
+                graph.steps.iter().map(|(_, incoming)| incoming.len()).sum(),
+            ),
+        }
+    }
+}
+
+impl Graph {
+    fn steer(&self, inputs: &[f32]) -> Vec2 {
+        if inputs.len() != self.bias || self.bias >= self.num_nodes {
+            return Vec2::ZERO;
+        }
+
+        let mut values = vec![0.0; self.num_nodes];
+        values[..self.bias].copy_from_slice(inputs);
+        values[self.bias] = 1.0;
+        for (node, incoming) in self.steps.iter() {
+            let sum: f32 = incoming.iter().map(|(from, w)| values[*from] * w).sum();
+            values[*node] = sum.tanh();
+        }
+        let mut outputs = self.outputs.iter().map(|o| values[*o]);
+
+        Vec2::new(outputs.next().unwrap_or(0.0), outputs.next().unwrap_or(0.0))
+    }
+}
+
+fn steer_layered(hidden: usize, weights: &[f32], inputs: &[f32]) -> Vec2 {
+    let num_inputs = inputs.len();
+    if hidden == 0 || weights.len() != (num_inputs + 1) * hidden + (hidden + 1) * OUTPUTS {
+        return Vec2::ZERO;
+    }
+
+    // Each neuron's weights are followed by its bias
+    let (input_weights, output_weights) = weights.split_at((num_inputs + 1) * hidden);
+    let hidden_values: Vec<f32> = input_weights
+        .chunks_exact(num_inputs + 1)
+        .map(|w| neuron(w, inputs))
+        .collect();
+    let mut outputs = output_weights
+        .chunks_exact(hidden + 1)
+        .map(|w| neuron(w, &hidden_values));
+
+    Vec2::new(outputs.next().unwrap_or(0.0), outputs.next().unwrap_or(0.0))
+}
+
+fn neuron(weights: &[f32], inputs: &[f32]) -> f32 {
+    let (bias, weights) = weights.split_last().unwrap();
+    let sum: f32 = weights.iter().zip(inputs).map(|(w, x)| w * x).sum();
+    (sum + bias).tanh()
+}
+
+/// Network inputs: for each target its offset as a fraction of the perception radius
+/// and how close it is from 0 at the edge to 1 on top, all 0 when nothing is seen,
+/// then the agent's health fraction and velocity
+pub fn senses(
+    pos: Vec2,
+    targets: &[(Option<Vec2>, f32)],
+    health: f32,
+    velocity: Vec2,
+    config: &SimConfig,
+) -> Vec<f32> {
+    let mut inputs = Vec::with_capacity(targets.len() * INPUTS_PER_TARGET + SELF_INPUTS);
+    for (target, radius) in targets {
+        match target {
+            Some(target) if *radius > 0.0 => {
+                let offset = (*target - pos) / *radius;
+                inputs.extend([offset.x, offset.y, 1.0 - offset.length().min(1.0)]);
+            }
+            _ => inputs.extend([0.0; INPUTS_PER_TARGET]),
+        }
+    }
+    inputs.extend([health / config.boid_max_health, velocity.x, velocity.y]);
+    inputs
+}
+
+/// Length of what `senses` returns for an agent of the given kind
+pub fn num_inputs(is_predator: bool) -> usize {
+    let targets = if is_predator {
+        PREDATOR_TARGETS
+    } else {
+        BOID_TARGETS
+    };
+    targets * INPUTS_PER_TARGET + SELF_INPUTS
+}
+
+fn num_weights(hidden: usize, is_predator: bool) -> usize {
+    (num_inputs(is_predator) + 1) * hidden + (hidden + 1) * OUTPUTS
+}
+
+pub fn weight_gene_name(index: usize) -> String {
+    format!("brain_{}", index)
+}
+
+/// Genes of an agent of the given kind: the configured ones, followed by
+/// a `brain_weight_gene` per network weight when the network controller is used
+pub fn gene_specs(config: &SimConfig, is_predator: bool) -> Cow<'_, [GeneSpec]> {
+    let genes = if is_predator {
+        &config.predator_genes
+    } else {
+        &config.boid_genes
+    };
+    let Controller::Network { hidden } = config.controller else {
+        return Cow::Borrowed(genes);
+    };
+
+    let mut specs = genes.clone();
+    specs.extend((0..num_weights(hidden, is_predator)).map(|i| GeneSpec {
+        name: Cow::Owned(weight_gene_name(i)),
+        ..config.brain_weight_gene.clone()
+    }));
+    Cow::Owned(specs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn layered_forward_pass() {
+        // One input, one hidden neuron, each neuron's weights followed by its bias
+        let weights = vec![0.5, 0.1, 2.0, -0.3, -1.0, 0.2];
+        let brain = Brain::Layered { hidden: 1, weights };
+        let x = 0.7_f32;
+        let h = (0.5 * x + 0.1).tanh();
+        let steering = brain.steer(&[x]);
+        assert!((steering.x - (2.0 * h - 0.3).tanh()).abs() < 1e-6);
+        assert!((steering.y - (-h + 0.2).tanh()).abs() < 1e-6);
+        assert_eq!(brain.complexity(), (1, 6));
+    }
+
+    #[test]
+    fn mismatched_weights_steer_nowhere() {
+        let brain = Brain::Layered {
+            hidden: 1,
+            weights: vec![1.0; 5],
+        };
+        assert_eq!(brain.steer(&[1.0]), Vec2::ZERO);
+        assert_eq!(Brain::None.steer(&[1.0]), Vec2::ZERO);
+    }
+
+    #[test]
+    fn senses_match_the_network_inputs() {
+        let config = SimConfig::default();
+        for is_predator in [false, true] {
+            let num_targets = if is_predator {
+                PREDATOR_TARGETS
+            } else {
+                BOID_TARGETS
+            };
+            let targets = vec![(None, 10.0); num_targets];
+            let inputs = senses(Vec2::ZERO, &targets, 50.0, Vec2::X, &config);
+            assert_eq!(inputs.len(), num_inputs(is_predator));
+        }
+
+        // Offset as a fraction of the radius, closeness falls off to 0 at the edge
+        let inputs = senses(
+            Vec2::ZERO,
+            &[(Some(Vec2::new(5.0, 0.0)), 10.0)],
+            config.boid_max_health,
+            Vec2::ZERO,
+            &config,
+        );
+        assert_eq!(inputs, vec![0.5, 0.0, 0.5, 1.0, 0.0, 0.0]);
+    }
+
+    #[test]
+    fn network_genes_follow_the_controller() {
+        let mut config = SimConfig::default();
+        assert_eq!(gene_specs(&config, false).len(), config.boid_genes.len());
+
+        config.controller = Controller::Network { hidden: 2 };
+        let specs = gene_specs(&config, true);
+        assert_eq!(
+            specs.len(),
+            config.predator_genes.len() + num_weights(2, true)
+        );
+        assert_eq!(
+            specs.last().unwrap().name,
+            weight_gene_name(num_weights(2, true) - 1)
+        );
+    }
+}
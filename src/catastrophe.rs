@@ -0,0 +1,214 @@
+use std::fmt;
+
+use bevy::math::vec2;
+use bevy::prelude::*;
+use rand::seq::index::sample;
+use rand::Rng;
+
+use crate::boid::{BoidDeathFoodSpawnEvent, PreyFilter};
+use crate::clock::on_sim_timer;
+use crate::elements::{Food, PoisonBundle};
+use crate::stats::SimulationStats;
+use crate::utils::world_distance_squared;
+use crate::*;
+
+pub struct CatastrophePlugin;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CatastropheKind {
+    /// Wipes out food
+    Drought,
+    /// Turns food into poison
+    PoisonBloom,
+    /// Kills boids, their bodies drop food like any other death
+    Plague,
+}
+
+/// A catastrophe striking the circle at `center`, or the whole world when `radius` is 0
+#[derive(Event, Debug, Clone, Copy)]
+pub struct CatastropheEvent {
+    pub kind: CatastropheKind,
+    pub center: Vec2,
+    pub radius: f32,
+    /// Fraction of what's in the area that is hit
+    pub severity: f32,
+}
+
+impl Plugin for CatastrophePlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(
+            FixedUpdate,
+            (
+                roll_catastrophes.run_if(on_sim_timer(CATASTROPHE_CHECK_INTERVAL)),
+                handle_catastrophe_events,
+            )
+                .chain()
+                .in_set(SimSet::Catastrophe)
+                .run_if(in_state(SimState::Simulating)),
+        )
+        .add_event::<CatastropheEvent>();
+    }
+}
+
+fn roll_catastrophes(
+    config: Res<SimConfig>,
+    mut rng: ResMut<SimRng>,
+    mut writer: EventWriter<CatastropheEvent>,
+) {
+    let rng = &mut rng.0;
+    for kind in CatastropheKind::ALL {
+        let params = kind.params(&config);
+        // Disabled kinds don't draw from the rng, so turning one off leaves the others' runs as they were
+        if params.frequency <= 0.0 {
+            continue;
+        }
+        // `frequency` is per sim-minute
+        let chance = params.frequency * CATASTROPHE_CHECK_INTERVAL / 60.0;
+        if rng.gen_range(0.0..1.0) >= chance {
+            continue;
+        }
+
+        let center = vec2(
+            rng.gen_range(-config.world_w..config.world_w),
+            rng.gen_range(-config.world_h..config.world_h),
+        );
+        writer.send(CatastropheEvent {
+            kind,
+            center,
+            radius: params.radius,
+            severity: params.severity,
+        });
+    }
+}
+
+fn handle_catastrophe_events(
+    spawner: Spawner,
+    mut stats: ResMut<SimulationStats>,
+    mut reader: EventReader<CatastropheEvent>,
+    mut death_writer: EventWriter<BoidDeathFoodSpawnEvent>,
+    food_query: Query<(Entity, &Transform), With<Food>>,
+    boids_query: Query<(Entity, &Transform), PreyFilter>,
+) {
+    let Spawner {
+        mut commands,
+        clock,
+        config,
+        mut rng,
+        handle,
+    } = spawner;
+    let rng = &mut rng.0;
+    for event in reader.read() {
+        let num_hit = match event.kind {
+            CatastropheKind::Drought | CatastropheKind::PoisonBloom => {
+                let hit: Vec<(Entity, Vec2)> = food_query
+                    .iter()
+                    .map(|(e, t)| (e, t.translation.truncate()))
+                    .filter(|(_, pos)| event.contains(*pos, &config))
+                    .collect();
+                let hit = pick(rng, &hit, event.severity);
+                for (e, pos) in hit.iter() {
+                    commands.entity(*e).despawn();
+                    if event.kind == CatastropheKind::PoisonBloom {
+                        commands.spawn(PoisonBundle::new(
+                            (*pos).into(),
+                            handle.atlas(),
+                            clock.tick,
+                            &config,
+                        ));
+                    }
+                }
+                hit.len()
+            }
+            CatastropheKind::Plague => {
+                let hit: Vec<(Entity, Vec2)> = boids_query
+                    .iter()
+                    .map(|(e, t)| (e, t.translation.truncate()))
+                    .filter(|(_, pos)| event.contains(*pos, &config))
+                    .collect();
+                let hit = pick(rng, &hit, event.severity);
+                // Dead like any other death, before anyone moves this tick
+                for (e, pos) in hit.iter() {
+                    commands.entity(*e).despawn();
+                    death_writer.send(BoidDeathFoodSpawnEvent(*pos));
+                }
+                hit.len()
+            }
+        };
+
+        let label = if event.radius > 0.0 {
+            format!(
+                "{} at ({:.0}, {:.0}), {} hit",
+                event.kind, event.center.x, event.center.y, num_hit
+            )
+        } else {
+            format!("{}, {} hit", event.kind, num_hit)
+        };
+        info!("Catastrophe at {:.1}s: {}", clock.elapsed_secs(), label);
+        stats.add_marker(clock.elapsed_secs(), format!("Catastrophe: {}", label));
+    }
+}
+
+/// Random `fraction` of `items`
+fn pick<T: Copy>(rng: &mut impl Rng, items: &[T], fraction: f32) -> Vec<T> {
+    let amount = (items.len() as f32 * fraction).round() as usize;
+    sample(rng, items.len(), amount)
+        .iter()
+        .map(|i| items[i])
+        .collect()
+}
+
+impl CatastropheKind {
+    pub const ALL: [CatastropheKind; 3] = [
+        CatastropheKind::Drought,
+        CatastropheKind::PoisonBloom,
+        CatastropheKind::Plague,
+    ];
+
+    pub fn params(self, config: &SimConfig) -> &CatastropheParams {
+        match self {
+            CatastropheKind::Drought => &config.drought,
+            CatastropheKind::PoisonBloom => &config.poison_bloom,
+            CatastropheKind::Plague => &config.plague,
+        }
+    }
+}
+
+impl CatastropheEvent {
+    fn contains(&self, pos: Vec2, config: &SimConfig) -> bool {
+        self.radius <= 0.0
+            || world_distance_squared(self.center, pos, config) <= self.radius * self.radius
+    }
+}
+
+impl fmt::Display for CatastropheKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(self, f)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn areas_wrap_around_on_a_torus() {
+        let mut config = SimConfig {
+            world_w: 100.0,
+            world_h: 100.0,
+            topology: Topology::Torus,
+            ..default()
+        };
+        let event = CatastropheEvent {
+            kind: CatastropheKind::Plague,
+            center: vec2(95.0, 0.0),
+            radius: 20.0,
+            severity: 1.0,
+        };
+        assert!(event.contains(vec2(-90.0, 5.0), &config));
+        assert!(!event.contains(vec2(-70.0, 0.0), &config));
+
+        config.topology = Topology::Walls;
+        assert!(!event.contains(vec2(-90.0, 5.0), &config));
+        assert!(event.contains(vec2(80.0, 0.0), &config));
+    }
+}
@@ -0,0 +1,81 @@
+use std::path::PathBuf;
+
+use clap::Parser;
+
+use crate::*;
+
+/// Boid ecosystem simulation
+#[derive(Parser, Debug)]
+#[command(version, about)]
+pub struct Cli {
+    /// Seed for the simulation RNG, overrides the config file
+    #[arg(long)]
+    pub seed: Option<u64>,
+
+    /// Path of the config file
+    #[arg(long, default_value = CONFIG_PATH)]
+    pub config: PathBuf,
+
+    /// Run without a window or renderer
+    #[arg(long)]
+    pub headless: bool,
+
+    /// Number of simulation ticks to run in headless mode, counted from the restored snapshot's
+    /// tick when resuming
+    #[arg(long, default_value_t = HEADLESS_NUM_TICKS, requires = "headless")]
+    pub ticks: u64,
+
+    /// Path the stats csv is written to at the end of a headless run
+    #[arg(long, default_value = HEADLESS_STATS_PATH, requires = "headless")]
+    pub stats_out: PathBuf,
+
+    /// World snapshot to start the simulation from
+    #[arg(long)]
+    pub snapshot_in: Option<PathBuf>,
+
+    /// Resume from the newest autosave checkpoint, if there is one
+    #[arg(long, conflicts_with = "snapshot_in")]
+    pub resume: bool,
+
+    /// Scenario file with timed and triggered events to run
+    #[arg(long)]
+    pub scenario: Option<PathBuf>,
+
+    /// Path a world snapshot is written to at the end of a headless run
+    #[arg(long, requires = "headless")]
+    pub snapshot_out: Option<PathBuf>,
+
+    /// Path the ancestry tree is written to as Newick/NHX at the end of a headless run
+    #[arg(long, requires = "headless")]
+    pub tree_out: Option<PathBuf>,
+
+    /// Window size, e.g. 1600x1000, overrides the config file
+    #[arg(long, value_name = "WxH", value_parser = parse_window_size)]
+    pub window: Option<(usize, usize)>,
+}
+
+impl Cli {
+    /// Applies the command line overrides on top of the loaded config
+    pub fn apply(&self, config: &mut SimConfig) {
+        if let Some(seed) = self.seed {
+            config.seed = seed;
+        }
+        if let Some((w, h)) = self.window {
+            config.window_width = w;
+            config.window_height = h;
+        }
+    }
+}
+
+fn parse_window_size(value: &str) -> Result<(usize, usize), String> {
+    let (w, h) = value
+        .split_once('x')
+        .ok_or_else(|| format!("expected WxH, got `{}`", value))?;
+    let w = w.parse().map_err(|_| format!("invalid width `{}`", w))?;
+    let h = h.parse().map_err(|_| format!("invalid height `{}`", h))?;
+    if w == 0 || h == 0 {
+        return Err("width and height must be greater than 0".to_string());
+    }
+
+    Ok((w, h))
+}
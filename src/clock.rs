@@ -0,0 +1,76 @@
+use std::time::Duration;
+
+use bevy::prelude::*;
+
+use crate::*;
+
+pub struct ClockPlugin;
+
+/// Simulated time, advanced by a fixed step every `FixedUpdate` tick.
+/// Ages, cooldowns and timers are measured against it instead of the wall clock.
+#[derive(Resource, Default)]
+pub struct SimClock {
+    pub tick: u64,
+}
+
+impl Plugin for ClockPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(SimClock::default())
+            .insert_resource(Time::<Fixed>::from_hz(SIM_TICK_RATE as f64))
+            .add_systems(Startup, set_sim_speed)
+            .add_systems(
+                FixedUpdate,
+                advance_sim_clock
+                    .in_set(SimSet::Clock)
+                    .run_if(in_state(SimState::Simulating)),
+            );
+    }
+}
+
+fn set_sim_speed(config: Res<SimConfig>, mut time: ResMut<Time<Virtual>>) {
+    // Allow enough virtual time per frame to keep up with the fastest speed
+    time.set_max_delta(Duration::from_secs_f32(0.25 * MAX_SIM_SPEED));
+    time.set_relative_speed(config.sim_speed);
+}
+
+fn advance_sim_clock(mut clock: ResMut<SimClock>) {
+    clock.tick += 1;
+}
+
+/// Run condition that is active on a regular interval of simulated time.
+/// Derived from the tick count alone, so it stays in phase when a snapshot is restored.
+pub fn on_sim_timer(secs: f32) -> impl Fn(Res<SimClock>) -> bool + Clone {
+    move |clock: Res<SimClock>| clock.is_interval(secs)
+}
+
+/// Same as `on_sim_timer`, with the interval read from the live `SimConfig` on every check
+pub fn on_config_timer(
+    interval: fn(&SimConfig) -> f32,
+) -> impl Fn(Res<SimClock>, Res<SimConfig>) -> bool + Clone {
+    move |clock: Res<SimClock>, config: Res<SimConfig>| clock.is_interval(interval(&config))
+}
+
+impl SimClock {
+    pub fn delta_secs(&self) -> f32 {
+        1.0 / SIM_TICK_RATE
+    }
+
+    pub fn delta(&self) -> Duration {
+        Duration::from_secs_f32(self.delta_secs())
+    }
+
+    pub fn elapsed_secs(&self) -> f32 {
+        self.secs_since(0)
+    }
+
+    /// Whether the current tick falls on an interval of `secs` simulated seconds
+    pub fn is_interval(&self, secs: f32) -> bool {
+        let interval_ticks = (secs * SIM_TICK_RATE).round().max(1.0) as u64;
+        self.tick.is_multiple_of(interval_ticks)
+    }
+
+    /// Simulated seconds elapsed since the given tick
+    pub fn secs_since(&self, tick: u64) -> f32 {
+        (self.tick.saturating_sub(tick) as f64 / SIM_TICK_RATE as f64) as f32
+    }
+}
@@ -0,0 +1,100 @@
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use bevy::prelude::*;
+use bevy::time::common_conditions::on_real_timer;
+
+use crate::clock::SimClock;
+use crate::stats::SimulationStats;
+use crate::*;
+
+/// Fields only read at startup, edits to them wait for a restart
+const RESTART_FIELDS: [&str; 3] = ["seed", "window_width", "window_height"];
+
+/// Watches the config file and applies the fields that change in it to the live `SimConfig`,
+/// leaving command line overrides and scenario changes to other fields alone
+pub struct ConfigReloadPlugin {
+    pub path: PathBuf,
+    /// Config as read from the file, before command line overrides
+    pub file_config: SimConfig,
+}
+
+#[derive(Resource)]
+struct ConfigWatcher {
+    path: PathBuf,
+    last_modified: Option<SystemTime>,
+    file_config: SimConfig,
+}
+
+impl Plugin for ConfigReloadPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(ConfigWatcher {
+            path: self.path.clone(),
+            last_modified: get_modified_time(&self.path),
+            file_config: self.file_config.clone(),
+        })
+        .add_systems(
+            Update,
+            reload_config
+                .run_if(in_state(SimState::Simulating))
+                .run_if(on_real_timer(Duration::from_secs_f32(CONFIG_POLL_RATE))),
+        );
+    }
+}
+
+fn reload_config(
+    mut watcher: ResMut<ConfigWatcher>,
+    mut config: ResMut<SimConfig>,
+    mut stats: ResMut<SimulationStats>,
+    mut time: ResMut<Time<Virtual>>,
+    clock: Res<SimClock>,
+) {
+    let modified = get_modified_time(&watcher.path);
+    if modified.is_none() || modified == watcher.last_modified {
+        return;
+    }
+    watcher.last_modified = modified;
+
+    let file_config = match SimConfig::load(&watcher.path) {
+        Ok(file_config) => file_config,
+        Err(e) => {
+            error!("{}: {}, keeping current config", watcher.path.display(), e);
+            return;
+        }
+    };
+
+    let (restart_changes, file_changes): (Vec<_>, Vec<_>) = watcher
+        .file_config
+        .changed_fields(&file_config)
+        .into_iter()
+        .partition(|(name, _)| RESTART_FIELDS.contains(&name.as_str()));
+    watcher.file_config = file_config;
+    if !restart_changes.is_empty() {
+        warn!("Window size and seed changes only take effect on restart");
+    }
+    let new_config = match config.with_fields(&file_changes) {
+        Ok(new_config) => new_config,
+        Err(e) => {
+            error!("{}: {}, keeping current config", watcher.path.display(), e);
+            return;
+        }
+    };
+
+    let changes = config.diff(&new_config);
+    if changes.is_empty() {
+        return;
+    }
+    if new_config.sim_speed != config.sim_speed {
+        time.set_relative_speed(new_config.sim_speed);
+    }
+
+    for change in changes.iter() {
+        info!("Config change at {:.1}s: {}", clock.elapsed_secs(), change);
+    }
+    stats.add_marker(clock.elapsed_secs(), changes.join(", "));
+    *config = new_config;
+}
+
+fn get_modified_time(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|m| m.modified()).ok()
+}
@@ -1,3 +1,15 @@
+use std::borrow::Cow;
+use std::collections::HashSet;
+use std::fmt;
+use std::path::Path;
+
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+// Config file
+pub const CONFIG_PATH: &str = "config.ron";
+pub const CONFIG_POLL_RATE: f32 = 1.0;
+
 // Window
 pub const WW: usize = 1600;
 pub const WH: usize = 1000;
@@ -12,8 +24,13 @@ pub const BOID_SPRITE_SCALE: f32 = 1.5;
 pub const CONSUMABLE_SPRITE_SCALE: f32 = 1.0;
 
 // Sim
+pub const SIM_SEED: u64 = 42;
+pub const SIM_TICK_RATE: f32 = 60.0;
+pub const SIM_SPEED: f32 = 1.0;
+pub const MAX_SIM_SPEED: f32 = 16.0;
 pub const WORLD_W: f32 = 1305.0;
 pub const WORLD_H: f32 = 730.0;
+pub const TOPOLOGY: Topology = Topology::Soft;
 pub const NUM_BOIDS: usize = 100;
 pub const NUM_PREDATORS: usize = 10;
 pub const NUM_FOOD: usize = 600;
@@ -22,15 +39,186 @@ pub const NUM_POISON: usize = 150;
 // Boids
 pub const BOID_COLLISION_RADIUS: f32 = 8.0;
 pub const BOID_MAX_HEALTH: f32 = 100.0;
+pub const BOID_BASE_SPEED: f32 = 60.0;
 pub const BOID_TICK_DAMAGE: f32 = 4.0;
 pub const BOID_REPLICATE_INTERVAL: f32 = 5.0;
 pub const BOID_REPLICATE_PROBABILITY: f32 = 0.2;
 pub const BOID_MUTATION_THRESHOLD: f32 = 0.8;
+pub const REPRODUCTION: Reproduction = Reproduction::Asexual;
+pub const MATE_PERCEPTION_RADIUS: f32 = 300.0;
+pub const MATING_COST: f32 = 5.0;
+pub const CONTROLLER: Controller = Controller::Pull;
+/// Steering from a network output of 1, about the pull of an item at a typical perception distance
+pub const BRAIN_OUTPUT_SCALE: f32 = 100.0;
 
 // Predators
 pub const BOID_NUTRITION: f32 = 30.0;
 pub const PREDATOR_COLLISION_RADIUS: f32 = 10.0;
 
+// Genes, `Dna` and `PredatorDna` read their fields from these
+/// Chance for each gene of a child to mutate, evolves like any other gene
+pub const MUTATION_RATE_GENE: &str = "mutation_rate";
+/// Multiplier of every gene's `sigma` in a child
+pub const MUTATION_SCALE_GENE: &str = "mutation_scale";
+pub const BOID_GENES: [GeneSpec; 10] = [
+    GeneSpec {
+        name: Cow::Borrowed("steering_force"),
+        min: 0.0,
+        max: 0.05,
+        init: (0.0005, 0.005),
+        sigma: 0.001,
+        mutation_rate: None,
+        plot: Cow::Borrowed("steering"),
+    },
+    GeneSpec {
+        name: Cow::Borrowed("speed"),
+        min: 10.0,
+        max: 200.0,
+        init: (30.0, 90.0),
+        sigma: 6.0,
+        mutation_rate: None,
+        plot: Cow::Borrowed("speed"),
+    },
+    GeneSpec {
+        name: Cow::Borrowed("food_pull"),
+        min: -5.0,
+        max: 5.0,
+        init: (-1.5, 1.5),
+        sigma: 0.1,
+        mutation_rate: None,
+        plot: Cow::Borrowed("affinity"),
+    },
+    GeneSpec {
+        name: Cow::Borrowed("poison_pull"),
+        min: -5.0,
+        max: 5.0,
+        init: (-1.5, 1.5),
+        sigma: 0.1,
+        mutation_rate: None,
+        plot: Cow::Borrowed("affinity"),
+    },
+    GeneSpec {
+        name: Cow::Borrowed("predator_pull"),
+        min: -5.0,
+        max: 5.0,
+        init: (-1.5, 1.5),
+        sigma: 0.1,
+        mutation_rate: None,
+        plot: Cow::Borrowed("affinity"),
+    },
+    GeneSpec {
+        name: Cow::Borrowed("food_perception_radius"),
+        min: 0.0,
+        max: 400.0,
+        init: (50.0, 120.0),
+        sigma: 5.0,
+        mutation_rate: None,
+        plot: Cow::Borrowed("perception"),
+    },
+    GeneSpec {
+        name: Cow::Borrowed("poison_perception_radius"),
+        min: 0.0,
+        max: 400.0,
+        init: (50.0, 120.0),
+        sigma: 5.0,
+        mutation_rate: None,
+        plot: Cow::Borrowed("perception"),
+    },
+    GeneSpec {
+        name: Cow::Borrowed("predator_perception_radius"),
+        min: 0.0,
+        max: 400.0,
+        init: (50.0, 120.0),
+        sigma: 5.0,
+        mutation_rate: None,
+        plot: Cow::Borrowed("perception"),
+    },
+    GeneSpec {
+        name: Cow::Borrowed(MUTATION_RATE_GENE),
+        min: 0.0,
+        max: 1.0,
+        init: (0.1, 0.3),
+        sigma: 0.02,
+        mutation_rate: None,
+        plot: Cow::Borrowed("mutation"),
+    },
+    GeneSpec {
+        name: Cow::Borrowed(MUTATION_SCALE_GENE),
+        min: 0.0,
+        max: 5.0,
+        init: (0.8, 1.2),
+        sigma: 0.1,
+        mutation_rate: None,
+        plot: Cow::Borrowed("mutation"),
+    },
+];
+/// Template of every network weight gene, named `brain_<index>`
+pub const BRAIN_WEIGHT_GENE: GeneSpec = GeneSpec {
+    name: Cow::Borrowed("brain"),
+    min: -4.0,
+    max: 4.0,
+    init: (-1.0, 1.0),
+    sigma: 0.2,
+    mutation_rate: None,
+    plot: Cow::Borrowed("brain"),
+};
+pub const PREDATOR_GENES: [GeneSpec; 6] = [
+    GeneSpec {
+        name: Cow::Borrowed("steering_force"),
+        min: 0.0,
+        max: 0.05,
+        init: (0.0005, 0.005),
+        sigma: 0.001,
+        mutation_rate: None,
+        plot: Cow::Borrowed("steering"),
+    },
+    GeneSpec {
+        name: Cow::Borrowed("speed"),
+        min: 10.0,
+        max: 200.0,
+        init: (30.0, 90.0),
+        sigma: 6.0,
+        mutation_rate: None,
+        plot: Cow::Borrowed("speed"),
+    },
+    GeneSpec {
+        name: Cow::Borrowed("prey_pull"),
+        min: -5.0,
+        max: 5.0,
+        init: (-1.0, 1.0),
+        sigma: 0.1,
+        mutation_rate: None,
+        plot: Cow::Borrowed("affinity"),
+    },
+    GeneSpec {
+        name: Cow::Borrowed("prey_perception"),
+        min: 0.0,
+        max: 400.0,
+        init: (30.0, 100.0),
+        sigma: 5.0,
+        mutation_rate: None,
+        plot: Cow::Borrowed("perception"),
+    },
+    GeneSpec {
+        name: Cow::Borrowed(MUTATION_RATE_GENE),
+        min: 0.0,
+        max: 1.0,
+        init: (0.1, 0.3),
+        sigma: 0.02,
+        mutation_rate: None,
+        plot: Cow::Borrowed("mutation"),
+    },
+    GeneSpec {
+        name: Cow::Borrowed(MUTATION_SCALE_GENE),
+        min: 0.0,
+        max: 5.0,
+        init: (0.8, 1.2),
+        sigma: 0.1,
+        mutation_rate: None,
+        plot: Cow::Borrowed("mutation"),
+    },
+];
+
 // Colors
 pub const COLOR_FOOD: (u8, u8, u8) = (142, 231, 112);
 pub const COLOR_POISON: (u8, u8, u8) = (235, 86, 75);
@@ -51,10 +239,907 @@ pub const CONSUMABLE_REPLICATION_RATE: f32 = 0.2;
 pub const CONSUMABLE_DECAY_RATE: f32 = 1.5;
 pub const REPLICATION_COOLDOWN: f32 = 2.0;
 
+// Obstacles
+pub const OBSTACLE_AVOID_DISTANCE: f32 = 30.0;
+pub const OBSTACLE_AVOID_FORCE: f32 = 1.0;
+/// Random positions tried before giving up on spawning something outside the obstacles
+pub const MAX_SPAWN_TRIES: usize = 100;
+
+// Biomes
+pub const BIOME_MAP: BiomeMapSource = BiomeMapSource::Uniform;
+pub const BIOME_PLAINS: BiomeParams = BiomeParams {
+    food_growth: 1.0,
+    poison_growth: 1.0,
+    nutrition: 1.0,
+    speed: 1.0,
+    energy_cost: 1.0,
+    color: (58, 72, 90),
+};
+pub const BIOME_FERTILE: BiomeParams = BiomeParams {
+    food_growth: 2.0,
+    poison_growth: 0.5,
+    nutrition: 1.5,
+    speed: 1.0,
+    energy_cost: 1.0,
+    color: (52, 86, 72),
+};
+pub const BIOME_DESERT: BiomeParams = BiomeParams {
+    food_growth: 0.3,
+    poison_growth: 0.5,
+    nutrition: 0.7,
+    speed: 1.1,
+    energy_cost: 1.5,
+    color: (98, 86, 66),
+};
+pub const BIOME_SWAMP: BiomeParams = BiomeParams {
+    food_growth: 1.0,
+    poison_growth: 2.0,
+    nutrition: 1.0,
+    speed: 0.6,
+    energy_cost: 1.2,
+    color: (56, 70, 62),
+};
+/// Cell size a uniform biome map is split into when a region of it gets painted
+pub const BIOME_PAINT_CELL_SIZE: f32 = 25.0;
+
+// Seasons
+pub const SEASON_LENGTH: f32 = 60.0;
+pub const DAY_LENGTH: f32 = 20.0;
+pub const NIGHT_PERCEPTION: f32 = 0.6;
+pub const SEASON_SPRING: SeasonParams = SeasonParams {
+    food_growth: 1.5,
+    food_decay: 1.0,
+    poison_growth: 1.0,
+};
+pub const SEASON_SUMMER: SeasonParams = SeasonParams {
+    food_growth: 1.0,
+    food_decay: 1.5,
+    poison_growth: 1.5,
+};
+pub const SEASON_AUTUMN: SeasonParams = SeasonParams {
+    food_growth: 0.7,
+    food_decay: 1.0,
+    poison_growth: 1.0,
+};
+pub const SEASON_WINTER: SeasonParams = SeasonParams {
+    food_growth: 0.3,
+    food_decay: 0.5,
+    poison_growth: 0.5,
+};
+
+// Catastrophes
+/// Sim-seconds between catastrophe rolls
+pub const CATASTROPHE_CHECK_INTERVAL: f32 = 1.0;
+pub const DROUGHT: CatastropheParams = CatastropheParams {
+    frequency: 0.2,
+    severity: 0.8,
+    radius: 250.0,
+};
+pub const POISON_BLOOM: CatastropheParams = CatastropheParams {
+    frequency: 0.2,
+    severity: 0.5,
+    radius: 150.0,
+};
+pub const PLAGUE: CatastropheParams = CatastropheParams {
+    frequency: 0.1,
+    severity: 0.3,
+    radius: 0.0,
+};
+
+// Species
+pub const SPECIES_INTERVAL: f32 = 2.0;
+pub const SPECIES_THRESHOLD: f32 = 0.05;
+pub const COLOR_BY_SPECIES: bool = true;
+
+// Spatial index
+pub const SPATIAL_CELL_SIZE: f32 = 50.0;
+/// sqrt(20), squares back to exactly 20.0
+pub const BOID_SEPARATION_RADIUS: f32 = 4.472136;
+
 // Stats
 pub const STAT_COLLECTION_RATE: f32 = 1.0;
 pub const MAX_NUM_POINTS: usize = 8000;
 
+// Snapshots
+pub const SNAPSHOT_DIR: &str = "snapshots";
+pub const SNAPSHOT_VERSION: u32 = 8;
+pub const CHECKPOINT_DIR: &str = "checkpoints";
+pub const AUTOSAVE_INTERVAL: f32 = 10.0;
+pub const AUTOSAVE_KEEP: usize = 5;
+pub const TREE_DIR: &str = "trees";
+
+// Headless
+pub const HEADLESS_NUM_TICKS: u64 = 36000;
+pub const HEADLESS_STATS_PATH: &str = "stats.csv";
+
 // Graphs
 pub const PLOT_LINE_WIDTH: f32 = 1.5;
 pub const PLOT_ASPECT_RATIO: f32 = 1.8;
+
+/// Runtime simulation parameters, loaded from `CONFIG_PATH` at startup.
+/// Any field missing from the file falls back to the constant of the same name above.
+#[derive(Resource, Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SimConfig {
+    // Window
+    pub window_width: usize,
+    pub window_height: usize,
+
+    // Sim
+    pub seed: u64,
+    pub sim_speed: f32,
+    pub world_w: f32,
+    pub world_h: f32,
+    pub topology: Topology,
+    pub num_boids: usize,
+    pub num_predators: usize,
+    pub num_food: usize,
+    pub num_poison: usize,
+
+    // Boids
+    pub boid_collision_radius: f32,
+    pub boid_max_health: f32,
+    pub boid_base_speed: f32,
+    pub boid_tick_damage: f32,
+    pub boid_replicate_interval: f32,
+    pub boid_replicate_probability: f32,
+    /// Mutation rate is `1 - boid_mutation_threshold` for genomes without a `mutation_rate` gene
+    pub boid_mutation_threshold: f32,
+    pub reproduction: Reproduction,
+    pub mate_perception_radius: f32,
+    /// Health each parent pays for a child in sexual reproduction
+    pub mating_cost: f32,
+    pub controller: Controller,
+    pub brain_weight_gene: GeneSpec,
+
+    // Predators
+    pub boid_nutrition: f32,
+    pub predator_collision_radius: f32,
+
+    // Genes
+    pub boid_genes: Vec<GeneSpec>,
+    pub predator_genes: Vec<GeneSpec>,
+
+    // Colors
+    pub color_food: (u8, u8, u8),
+    pub color_poison: (u8, u8, u8),
+    pub color_boid: (u8, u8, u8),
+    pub color_predator: (u8, u8, u8),
+    pub color_predator_low_health: (u8, u8, u8),
+    pub color_boid_low_health: (u8, u8, u8),
+    pub color_background: (u8, u8, u8),
+
+    // Consumables
+    pub food_nutrition: f32,
+    pub poison_damage: f32,
+    pub food_decay_rate: f32,
+    pub poison_decay_rate: f32,
+    pub replication_radius_food: f32,
+    pub replication_radius_poison: f32,
+    pub consumable_replication_rate: f32,
+    pub consumable_decay_rate: f32,
+    pub replication_cooldown: f32,
+
+    // Obstacles
+    pub obstacles: Vec<ObstacleShape>,
+    pub obstacle_avoid_distance: f32,
+    pub obstacle_avoid_force: f32,
+
+    // Biomes
+    pub biome_map: BiomeMapSource,
+    pub biome_plains: BiomeParams,
+    pub biome_fertile: BiomeParams,
+    pub biome_desert: BiomeParams,
+    pub biome_swamp: BiomeParams,
+
+    // Seasons
+    /// Sim-seconds per season, 0 disables seasons
+    pub season_length: f32,
+    /// Sim-seconds per day and night together, 0 disables nights
+    pub day_length: f32,
+    pub night_perception: f32,
+    pub season_spring: SeasonParams,
+    pub season_summer: SeasonParams,
+    pub season_autumn: SeasonParams,
+    pub season_winter: SeasonParams,
+
+    // Catastrophes
+    pub drought: CatastropheParams,
+    pub poison_bloom: CatastropheParams,
+    pub plague: CatastropheParams,
+
+    // Species
+    /// Sim-seconds between clustering passes
+    pub species_interval: f32,
+    /// Largest normalized gene distance to a species' average genome for an agent to join it
+    pub species_threshold: f32,
+    /// Tints boids and predators by species instead of `color_boid` and `color_predator`
+    pub color_by_species: bool,
+
+    // Stats
+    pub stat_collection_rate: f32,
+
+    // Snapshots
+    /// Sim-minutes between checkpoints, 0 disables autosave
+    pub autosave_interval: f32,
+    pub autosave_keep: usize,
+}
+
+/// How the world edges behave for movement, perception and spawning
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Topology {
+    /// Boids bounce off the edges
+    Walls,
+    /// Edges wrap around, distances are measured across them
+    Torus,
+    /// Boids past an edge are steered back towards the center
+    Soft,
+}
+
+/// Static obstacle, positions and sizes in world units
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum ObstacleShape {
+    Circle {
+        center: (f32, f32),
+        radius: f32,
+    },
+    Rect {
+        center: (f32, f32),
+        size: (f32, f32),
+        /// Counterclockwise rotation in degrees
+        #[serde(default)]
+        angle: f32,
+    },
+}
+
+/// What turns what an agent sees into steering
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum Controller {
+    /// Closest food, poison and predator, or prey, weighted by the pull genes
+    Pull,
+    /// Feed-forward network with one hidden layer of `hidden` neurons, its weights are genes
+    Network { hidden: usize },
+    /// NEAT network whose topology evolves, each child gains a connection and a hidden node
+    /// with these chances
+    Neat { add_connection: f32, add_node: f32 },
+}
+
+/// How boids and predators replicate
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Reproduction {
+    /// Mutated copy of a single parent
+    Asexual,
+    /// Genes of the parent and the closest mate within `mate_perception_radius`, mutated
+    Sexual { crossover: Crossover },
+}
+
+/// How the genes of two parents are combined
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Crossover {
+    /// Each gene comes from either parent
+    Uniform,
+    /// Each gene is a random point between both parents' values
+    Blend,
+}
+
+/// A heritable trait, looked up by name
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct GeneSpec {
+    pub name: Cow<'static, str>,
+    pub min: f32,
+    pub max: f32,
+    /// Range the genes of the first generation are drawn from
+    pub init: (f32, f32),
+    /// Standard deviation of a mutation
+    pub sigma: f32,
+    /// Chance to mutate on replication, the parent's `mutation_rate` gene when left out
+    #[serde(default)]
+    pub mutation_rate: Option<f32>,
+    /// Graph the population average is drawn in, genes sharing one are drawn together
+    pub plot: Cow<'static, str>,
+}
+
+/// Where the biome grid comes from
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum BiomeMapSource {
+    /// Plains everywhere
+    Uniform,
+    /// Noise generated from `seed`, with square cells of `cell_size`
+    /// and patches roughly `scale` world units across
+    Procedural {
+        seed: u64,
+        cell_size: f32,
+        scale: f32,
+    },
+    /// One cell per pixel stretched over the world, each pixel picks the biome
+    /// whose color is closest
+    Image { path: String },
+}
+
+/// How a biome affects whatever is in it, every modifier scales the base value
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct BiomeParams {
+    pub food_growth: f32,
+    pub poison_growth: f32,
+    pub nutrition: f32,
+    pub speed: f32,
+    pub energy_cost: f32,
+    pub color: (u8, u8, u8),
+}
+
+/// How a season changes the environment, every modifier scales the base value
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct SeasonParams {
+    pub food_growth: f32,
+    pub food_decay: f32,
+    pub poison_growth: f32,
+}
+
+/// How often and how hard a kind of catastrophe strikes
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct CatastropheParams {
+    /// Average number per sim-minute, 0 disables it
+    pub frequency: f32,
+    /// Fraction of the food or boids in the area that is hit
+    pub severity: f32,
+    /// Radius of the area around a random center, 0 hits the whole world
+    pub radius: f32,
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Io(std::io::Error),
+    Parse(ron::error::SpannedError),
+    Invalid(Vec<String>),
+}
+
+impl SimConfig {
+    /// Reads and validates the config file, a missing file yields the defaults
+    pub fn load(path: &Path) -> Result<Self, ConfigError> {
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+
+        let contents = std::fs::read_to_string(path).map_err(ConfigError::Io)?;
+        let config: Self = ron::from_str(&contents).map_err(ConfigError::Parse)?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Human readable list of the fields that differ in `other`
+    pub fn diff(&self, other: &Self) -> Vec<String> {
+        let new_fields = other.fields();
+        self.fields()
+            .into_iter()
+            .zip(new_fields)
+            .filter(|((_, old), (_, new))| old != new)
+            .map(|((name, old), (_, new))| format!("{}: {} -> {}", name, old, new))
+            .collect()
+    }
+
+    /// Fields whose value differs in `other`, along with their value there
+    pub fn changed_fields(&self, other: &Self) -> Vec<(String, String)> {
+        self.fields()
+            .into_iter()
+            .zip(other.fields())
+            .filter(|((_, old), (_, new))| old != new)
+            .map(|(_, new)| new)
+            .collect()
+    }
+
+    /// Serialized value of a single field, None for unknown fields
+    pub fn field(&self, name: &str) -> Option<String> {
+        self.fields()
+            .into_iter()
+            .find(|(field, _)| field == name)
+            .map(|(_, value)| value)
+    }
+
+    /// Copy with one field replaced by a value in ron syntax, validated like a loaded config
+    pub fn with_field(&self, name: &str, value: &str) -> Result<Self, ConfigError> {
+        self.with_fields(&[(name.to_string(), value.to_string())])
+    }
+
+    /// Copy with several fields replaced, see `with_field`
+    pub fn with_fields(&self, values: &[(String, String)]) -> Result<Self, ConfigError> {
+        let unknown: Vec<String> = values
+            .iter()
+            .filter(|(name, _)| self.field(name).is_none())
+            .map(|(name, _)| format!("unknown field `{}`", name))
+            .collect();
+        if !unknown.is_empty() {
+            return Err(ConfigError::Invalid(unknown));
+        }
+
+        let fields: Vec<String> = self
+            .fields()
+            .into_iter()
+            .map(|(field, old)| {
+                let value = values
+                    .iter()
+                    .find(|(name, _)| *name == field)
+                    .map_or(&old, |(_, value)| value);
+                format!("{}: {},", field, value)
+            })
+            .collect();
+        let config: Self =
+            ron::from_str(&format!("({})", fields.join("\n"))).map_err(ConfigError::Parse)?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Every field name along with its serialized value
+    fn fields(&self) -> Vec<(String, String)> {
+        // One field per line, nested values stay on their field's line.
+        // Going through `ron::Value` instead would lose enum variant names.
+        let pretty = ron::ser::PrettyConfig::new().depth_limit(1);
+        let Ok(contents) = ron::ser::to_string_pretty(self, pretty) else {
+            return Vec::new();
+        };
+
+        contents
+            .lines()
+            .filter_map(|line| {
+                let (name, value) = line.trim().trim_end_matches(',').split_once(": ")?;
+                Some((name.to_string(), value.to_string()))
+            })
+            .collect()
+    }
+
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        let mut errors = Vec::new();
+        let mut check = |is_valid: bool, msg: &str| {
+            if !is_valid {
+                errors.push(msg.to_string());
+            }
+        };
+
+        check(
+            self.window_width > 0 && self.window_height > 0,
+            "window_width and window_height must be greater than 0",
+        );
+        check(
+            (1.0 / MAX_SIM_SPEED..=MAX_SIM_SPEED).contains(&self.sim_speed),
+            &format!(
+                "sim_speed must be between 1/MAX_SIM_SPEED and MAX_SIM_SPEED ({})",
+                MAX_SIM_SPEED
+            ),
+        );
+        check(
+            self.world_w > 0.0 && self.world_h > 0.0,
+            "world_w and world_h must be greater than 0",
+        );
+        check(
+            self.num_boids > 0
+                && self.num_predators > 0
+                && self.num_food > 0
+                && self.num_poison > 0,
+            "num_boids, num_predators, num_food and num_poison must be greater than 0",
+        );
+        check(
+            self.boid_collision_radius > 0.0 && self.predator_collision_radius > 0.0,
+            "boid_collision_radius and predator_collision_radius must be greater than 0",
+        );
+        check(
+            self.boid_max_health > 0.0,
+            "boid_max_health must be greater than 0",
+        );
+        check(
+            self.boid_base_speed > 0.0,
+            "boid_base_speed must be greater than 0",
+        );
+        check(
+            self.boid_tick_damage >= 0.0,
+            "boid_tick_damage must not be negative",
+        );
+        check(
+            (0.0..=1.0).contains(&self.boid_replicate_probability),
+            "boid_replicate_probability must be between 0 and 1",
+        );
+        check(
+            (0.0..=1.0).contains(&self.boid_mutation_threshold),
+            "boid_mutation_threshold must be between 0 and 1",
+        );
+        check(
+            match self.controller {
+                Controller::Pull => true,
+                Controller::Network { hidden } => (1..=64).contains(&hidden),
+                Controller::Neat {
+                    add_connection,
+                    add_node,
+                } => (0.0..=1.0).contains(&add_connection) && (0.0..=1.0).contains(&add_node),
+            },
+            "the network controller must have between 1 and 64 hidden neurons, \
+             the neat controller's add_connection and add_node must be between 0 and 1",
+        );
+        check(
+            {
+                let g = &self.brain_weight_gene;
+                g.min <= g.init.0
+                    && g.init.0 <= g.init.1
+                    && g.init.1 <= g.max
+                    && g.sigma >= 0.0
+                    && g.mutation_rate.is_none_or(|r| (0.0..=1.0).contains(&r))
+            },
+            "brain_weight_gene init must be a range within min and max, sigma must not be negative \
+             and mutation_rate must be between 0 and 1",
+        );
+        check(
+            self.mate_perception_radius > 0.0 && self.mating_cost >= 0.0,
+            "mate_perception_radius must be greater than 0 and mating_cost must not be negative",
+        );
+        check(
+            [&self.boid_genes, &self.predator_genes]
+                .iter()
+                .all(|genes| {
+                    let mut names = HashSet::new();
+                    genes.iter().all(|g| {
+                        names.insert(&g.name)
+                            && g.min <= g.init.0
+                            && g.init.0 <= g.init.1
+                            && g.init.1 <= g.max
+                            && g.sigma >= 0.0
+                            && g.mutation_rate.is_none_or(|r| (0.0..=1.0).contains(&r))
+                    })
+                }),
+            "gene names must be unique, init must be a range within min and max, \
+             sigma must not be negative and mutation_rate must be between 0 and 1",
+        );
+        check(
+            [&self.boid_genes, &self.predator_genes]
+                .iter()
+                .flat_map(|genes| genes.iter())
+                .all(|g| match g.name.as_ref() {
+                    MUTATION_RATE_GENE => g.min >= 0.0 && g.max <= 1.0,
+                    MUTATION_SCALE_GENE => g.min >= 0.0,
+                    _ => true,
+                }),
+            "the mutation_rate gene must stay between 0 and 1 and mutation_scale must not be negative",
+        );
+        // The mutation genes can be left out to fall back to `boid_mutation_threshold`
+        let is_required =
+            |g: &&GeneSpec| ![MUTATION_RATE_GENE, MUTATION_SCALE_GENE].contains(&g.name.as_ref());
+        check(
+            BOID_GENES
+                .iter()
+                .filter(is_required)
+                .all(|g| self.boid_genes.iter().any(|b| b.name == g.name))
+                && PREDATOR_GENES
+                    .iter()
+                    .filter(is_required)
+                    .all(|g| self.predator_genes.iter().any(|p| p.name == g.name)),
+            "boid_genes and predator_genes must include every gene of the defaults",
+        );
+        check(
+            self.boid_nutrition >= 0.0,
+            "boid_nutrition must not be negative",
+        );
+        // 0.1 and -0.1 are the min nutrition values for food/poison
+        check(
+            self.food_nutrition > 0.1,
+            "food_nutrition must be above 0.1",
+        );
+        check(
+            self.poison_damage < -0.1,
+            "poison_damage must be below -0.1",
+        );
+        check(
+            self.food_decay_rate <= 0.0,
+            "food_decay_rate must not be positive",
+        );
+        check(
+            self.poison_decay_rate >= 0.0,
+            "poison_decay_rate must not be negative",
+        );
+        check(
+            self.replication_radius_food > 0.0 && self.replication_radius_poison > 0.0,
+            "replication_radius_food and replication_radius_poison must be greater than 0",
+        );
+        check(
+            self.boid_replicate_interval > 0.0
+                && self.consumable_replication_rate > 0.0
+                && self.consumable_decay_rate > 0.0
+                && self.stat_collection_rate > 0.0,
+            "boid_replicate_interval, consumable_replication_rate, consumable_decay_rate and \
+             stat_collection_rate must be greater than 0",
+        );
+        check(
+            self.replication_cooldown >= 0.0,
+            "replication_cooldown must not be negative",
+        );
+        check(
+            self.obstacles.iter().all(|o| match *o {
+                ObstacleShape::Circle { radius, .. } => radius > 0.0,
+                ObstacleShape::Rect { size, .. } => size.0 > 0.0 && size.1 > 0.0,
+            }),
+            "obstacle radius and size must be greater than 0",
+        );
+        check(
+            self.obstacle_avoid_distance > 0.0,
+            "obstacle_avoid_distance must be greater than 0",
+        );
+        check(
+            match &self.biome_map {
+                BiomeMapSource::Uniform => true,
+                BiomeMapSource::Procedural {
+                    cell_size, scale, ..
+                } => *cell_size > 0.0 && *scale > 0.0,
+                BiomeMapSource::Image { path } => !path.is_empty(),
+            },
+            "biome_map cell_size and scale must be greater than 0, path must not be empty",
+        );
+        // Replication chance is 0.2 times the growth
+        check(
+            [
+                &self.biome_plains,
+                &self.biome_fertile,
+                &self.biome_desert,
+                &self.biome_swamp,
+            ]
+            .iter()
+            .all(|b| {
+                (0.0..=5.0).contains(&b.food_growth)
+                    && (0.0..=5.0).contains(&b.poison_growth)
+                    && b.nutrition > 0.0
+                    && b.speed > 0.0
+                    && b.energy_cost >= 0.0
+            }),
+            "biome food_growth and poison_growth must be between 0 and 5, nutrition and speed \
+             must be greater than 0 and energy_cost must not be negative",
+        );
+        check(
+            self.season_length >= 0.0 && self.day_length >= 0.0,
+            "season_length and day_length must not be negative",
+        );
+        check(
+            self.night_perception > 0.0,
+            "night_perception must be greater than 0",
+        );
+        check(
+            [
+                &self.season_spring,
+                &self.season_summer,
+                &self.season_autumn,
+                &self.season_winter,
+            ]
+            .iter()
+            .all(|s| s.food_growth >= 0.0 && s.food_decay >= 0.0 && s.poison_growth >= 0.0),
+            "season food_growth, food_decay and poison_growth must not be negative",
+        );
+        check(
+            [&self.drought, &self.poison_bloom, &self.plague]
+                .iter()
+                .all(|c| {
+                    (0.0..=60.0 / CATASTROPHE_CHECK_INTERVAL).contains(&c.frequency)
+                        && (0.0..=1.0).contains(&c.severity)
+                        && c.radius >= 0.0
+                }),
+            "catastrophe frequency must be between 0 and 60, severity between 0 and 1 \
+             and radius must not be negative",
+        );
+        check(
+            self.species_interval > 0.0 && (0.0..=1.0).contains(&self.species_threshold),
+            "species_interval must be greater than 0 and species_threshold between 0 and 1",
+        );
+        check(
+            self.autosave_interval >= 0.0,
+            "autosave_interval must not be negative",
+        );
+        check(
+            self.autosave_keep > 0,
+            "autosave_keep must be greater than 0",
+        );
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(ConfigError::Invalid(errors))
+        }
+    }
+}
+
+impl Default for SimConfig {
+    fn default() -> Self {
+        Self {
+            window_width: WW,
+            window_height: WH,
+            seed: SIM_SEED,
+            sim_speed: SIM_SPEED,
+            world_w: WORLD_W,
+            world_h: WORLD_H,
+            topology: TOPOLOGY,
+            num_boids: NUM_BOIDS,
+            num_predators: NUM_PREDATORS,
+            num_food: NUM_FOOD,
+            num_poison: NUM_POISON,
+            boid_collision_radius: BOID_COLLISION_RADIUS,
+            boid_max_health: BOID_MAX_HEALTH,
+            boid_base_speed: BOID_BASE_SPEED,
+            boid_tick_damage: BOID_TICK_DAMAGE,
+            boid_replicate_interval: BOID_REPLICATE_INTERVAL,
+            boid_replicate_probability: BOID_REPLICATE_PROBABILITY,
+            boid_mutation_threshold: BOID_MUTATION_THRESHOLD,
+            reproduction: REPRODUCTION,
+            controller: CONTROLLER,
+            brain_weight_gene: BRAIN_WEIGHT_GENE,
+            mate_perception_radius: MATE_PERCEPTION_RADIUS,
+            mating_cost: MATING_COST,
+            boid_nutrition: BOID_NUTRITION,
+            predator_collision_radius: PREDATOR_COLLISION_RADIUS,
+            boid_genes: BOID_GENES.to_vec(),
+            predator_genes: PREDATOR_GENES.to_vec(),
+            color_food: COLOR_FOOD,
+            color_poison: COLOR_POISON,
+            color_boid: COLOR_BOID,
+            color_predator: COLOR_PREDATOR,
+            color_predator_low_health: COLOR_PREDATOR_LOW_HEALTH,
+            color_boid_low_health: COLOR_BOID_LOW_HEALTH,
+            color_background: COLOR_BACKGROUND,
+            food_nutrition: FOOD_NUTRITION,
+            poison_damage: POISON_DAMAGE,
+            food_decay_rate: FOOD_DECAY_RATE,
+            poison_decay_rate: POISON_DECAY_RATE,
+            replication_radius_food: REPLICATION_RADIUS_FOOD,
+            replication_radius_poison: REPLICATION_RADIUS_POISON,
+            consumable_replication_rate: CONSUMABLE_REPLICATION_RATE,
+            consumable_decay_rate: CONSUMABLE_DECAY_RATE,
+            replication_cooldown: REPLICATION_COOLDOWN,
+            obstacles: Vec::new(),
+            obstacle_avoid_distance: OBSTACLE_AVOID_DISTANCE,
+            obstacle_avoid_force: OBSTACLE_AVOID_FORCE,
+            biome_map: BIOME_MAP,
+            biome_plains: BIOME_PLAINS,
+            biome_fertile: BIOME_FERTILE,
+            biome_desert: BIOME_DESERT,
+            biome_swamp: BIOME_SWAMP,
+            season_length: SEASON_LENGTH,
+            day_length: DAY_LENGTH,
+            night_perception: NIGHT_PERCEPTION,
+            season_spring: SEASON_SPRING,
+            season_summer: SEASON_SUMMER,
+            season_autumn: SEASON_AUTUMN,
+            season_winter: SEASON_WINTER,
+            drought: DROUGHT,
+            poison_bloom: POISON_BLOOM,
+            plague: PLAGUE,
+            species_interval: SPECIES_INTERVAL,
+            species_threshold: SPECIES_THRESHOLD,
+            color_by_species: COLOR_BY_SPECIES,
+            stat_collection_rate: STAT_COLLECTION_RATE,
+            autosave_interval: AUTOSAVE_INTERVAL,
+            autosave_keep: AUTOSAVE_KEEP,
+        }
+    }
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ConfigError::Io(e) => write!(f, "failed to read config: {}", e),
+            ConfigError::Parse(e) => write!(f, "failed to parse config: {}", e),
+            ConfigError::Invalid(errors) => {
+                write!(f, "invalid config:")?;
+                for e in errors {
+                    write!(f, "\n  - {}", e)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Validation errors of the default config with `change` applied
+    fn errors(change: impl FnOnce(&mut SimConfig)) -> Vec<String> {
+        let mut config = SimConfig::default();
+        change(&mut config);
+        match config.validate() {
+            Ok(()) => Vec::new(),
+            Err(ConfigError::Invalid(errors)) => errors,
+            Err(e) => panic!("unexpected error: {}", e),
+        }
+    }
+
+    fn assert_rejected(change: impl FnOnce(&mut SimConfig), msg: &str) {
+        let errors = errors(change);
+        assert!(
+            errors.iter().any(|e| e.starts_with(msg)),
+            "expected `{}` in {:?}",
+            msg,
+            errors
+        );
+    }
+
+    #[test]
+    fn defaults_and_shipped_config_are_valid() {
+        assert!(errors(|_| {}).is_empty());
+        SimConfig::load(Path::new("config.ron")).unwrap();
+    }
+
+    #[test]
+    fn rejects_out_of_range_values() {
+        assert_rejected(|c| c.window_width = 0, "window_width");
+        assert_rejected(|c| c.sim_speed = 0.0, "sim_speed");
+        assert_rejected(|c| c.sim_speed = MAX_SIM_SPEED * 2.0, "sim_speed");
+        assert!(errors(|c| c.sim_speed = MAX_SIM_SPEED).is_empty());
+        assert_rejected(
+            |c| c.boid_replicate_probability = 1.5,
+            "boid_replicate_probability",
+        );
+        assert_rejected(|c| c.food_nutrition = 0.0, "food_nutrition");
+        assert_rejected(|c| c.species_threshold = -0.1, "species_interval");
+        assert_rejected(|c| c.plague.severity = 2.0, "catastrophe");
+        assert_rejected(
+            |c| {
+                c.obstacles.push(ObstacleShape::Circle {
+                    center: (0.0, 0.0),
+                    radius: 0.0,
+                })
+            },
+            "obstacle radius",
+        );
+    }
+
+    #[test]
+    fn rejects_bad_controllers() {
+        assert_rejected(
+            |c| c.controller = Controller::Network { hidden: 0 },
+            "the network controller",
+        );
+        assert_rejected(
+            |c| {
+                c.controller = Controller::Neat {
+                    add_connection: 0.5,
+                    add_node: 1.5,
+                }
+            },
+            "the network controller",
+        );
+        assert_rejected(
+            |c| c.brain_weight_gene.init = (-5.0, 1.0),
+            "brain_weight_gene",
+        );
+        assert!(errors(|c| c.controller = Controller::Neat {
+            add_connection: 0.0,
+            add_node: 1.0,
+        })
+        .is_empty());
+    }
+
+    #[test]
+    fn rejects_bad_genes() {
+        assert_rejected(
+            |c| {
+                let duplicate = c.boid_genes[0].clone();
+                c.boid_genes.push(duplicate);
+            },
+            "gene names must be unique",
+        );
+        assert_rejected(|c| c.predator_genes[0].sigma = -1.0, "gene names");
+        assert_rejected(
+            |c| c.boid_genes.retain(|g| g.name != "speed"),
+            "boid_genes and predator_genes must include",
+        );
+        assert_rejected(
+            |c| {
+                let rate = c
+                    .boid_genes
+                    .iter_mut()
+                    .find(|g| g.name == MUTATION_RATE_GENE)
+                    .unwrap();
+                rate.max = 2.0;
+            },
+            "the mutation_rate gene",
+        );
+        // The mutation genes are optional
+        assert!(errors(|c| c
+            .boid_genes
+            .retain(|g| g.name != MUTATION_RATE_GENE && g.name != MUTATION_SCALE_GENE))
+        .is_empty());
+    }
+}
@@ -1,8 +1,14 @@
-use std::time::Duration;
-
-use bevy::{math::vec3, prelude::*, time::common_conditions::on_timer, utils::Instant};
+use bevy::{
+    math::{vec2, vec3},
+    prelude::*,
+};
 use rand::Rng;
 
+use crate::biome::BiomeMap;
+use crate::clock::on_config_timer;
+use crate::obstacles::{is_blocked, Obstacle};
+use crate::season::SeasonCycle;
+use crate::snapshot::ConsumableSnapshot;
 use crate::*;
 
 use self::utils::{get_color, limit_to_world};
@@ -15,8 +21,9 @@ pub struct Consumable(pub f32);
 pub struct Food;
 #[derive(Component)]
 pub struct Poison;
+/// Sim clock tick of the last replication
 #[derive(Component)]
-struct LastReplicationTs(Instant);
+pub struct LastReplicationTs(pub u64);
 
 #[derive(Bundle)]
 struct ConsumableBundle {
@@ -38,35 +45,40 @@ pub struct PoisonBundle {
 impl Plugin for ElementsPlugin {
     fn build(&self, app: &mut App) {
         app.add_systems(
-            Update,
+            FixedUpdate,
             (
-                replicate_consumables.run_if(on_timer(Duration::from_secs_f32(
-                    CONSUMABLE_REPLICATION_RATE,
-                ))),
-                decay_consumables.run_if(on_timer(Duration::from_secs_f32(CONSUMABLE_DECAY_RATE))),
+                replicate_consumables.run_if(on_config_timer(|c| c.consumable_replication_rate)),
+                decay_consumables.run_if(on_config_timer(|c| c.consumable_decay_rate)),
                 despawn_consumables,
-                update_color,
             )
+                .chain()
+                .in_set(SimSet::Elements)
                 .run_if(in_state(SimState::Simulating)),
-        );
+        )
+        .add_systems(Update, update_color.run_if(in_state(SimState::Simulating)));
     }
 }
 
 fn update_color(
+    config: Res<SimConfig>,
     mut consumable_query: Query<(&mut TextureAtlasSprite, &Consumable), With<Consumable>>,
 ) {
     for (mut sprite, c) in consumable_query.iter_mut() {
-        sprite.color = sprite.color.with_a(c.opacity());
+        sprite.color = sprite.color.with_a(c.opacity(&config));
     }
 }
 
-fn decay_consumables(mut consumable_query: Query<&mut Consumable, With<Consumable>>) {
+fn decay_consumables(
+    config: Res<SimConfig>,
+    cycle: Res<SeasonCycle>,
+    mut consumable_query: Query<&mut Consumable, With<Consumable>>,
+) {
     if consumable_query.is_empty() {
         return;
     }
 
     for mut c in consumable_query.iter_mut() {
-        c.decay()
+        c.decay(&config, cycle.params(&config))
     }
 }
 
@@ -82,56 +94,84 @@ fn despawn_consumables(
 }
 
 fn replicate_consumables(
-    mut commands: Commands,
-    handle: Res<GlobalTextureHandle>,
+    spawner: Spawner,
+    biomes: Res<BiomeMap>,
+    cycle: Res<SeasonCycle>,
     food_query: Query<With<Food>>,
     poison_query: Query<With<Poison>>,
     mut consumable_query: Query<
         (&Transform, &Consumable, &mut LastReplicationTs),
         With<Consumable>,
     >,
+    obstacles_query: Query<&Obstacle>,
 ) {
+    let Spawner {
+        mut commands,
+        clock,
+        config,
+        mut rng,
+        handle,
+    } = spawner;
     let num_food = food_query.iter().len();
     let num_poison = poison_query.iter().len();
-    let is_populate_food = num_food < NUM_FOOD;
-    let is_populate_poison = num_poison < NUM_POISON;
+    let is_populate_food = num_food < config.num_food;
+    let is_populate_poison = num_poison < config.num_poison;
 
-    let mut rng = rand::thread_rng();
+    let rng = &mut rng.0;
     for (transform, consumable, mut last_replication_ts) in consumable_query.iter_mut() {
-        if last_replication_ts.0.elapsed().as_secs_f32() < REPLICATION_COOLDOWN {
+        if clock.secs_since(last_replication_ts.0) < config.replication_cooldown {
             continue;
         }
-        if rng.gen_range(0.0..1.0) < 0.8 {
+        let pos = transform.translation.truncate();
+        let (biome, season) = (biomes.params(pos, &config), cycle.params(&config));
+        let growth = if consumable.is_food() {
+            biome.food_growth * season.food_growth
+        } else {
+            biome.poison_growth * season.poison_growth
+        };
+        // Base chance of 0.2, scaled by how well the biome and season grow this kind
+        if rng.gen_range(0.0..1.0) < 1.0 - 0.2 * f64::from(growth) {
             continue;
         }
 
         // Probability to replicate at the center > edges
-        let dist_to_center = transform
-            .translation
-            .truncate()
-            .distance_squared(Vec2::ZERO);
+        let dist_to_center = pos.distance_squared(Vec2::ZERO);
         if rng.gen_range(0.0..1.0) < dist_to_center / 1000000.0 {
             continue;
         }
 
         let replication_radius = if consumable.is_food() {
-            REPLICATION_RADIUS_FOOD
+            config.replication_radius_food
         } else {
-            REPLICATION_RADIUS_POISON
+            config.replication_radius_poison
         };
         let (x, y) = (transform.translation.x, transform.translation.y);
         let (x, y) = (
             x + rng.gen_range(-replication_radius..replication_radius),
             y + rng.gen_range(-replication_radius..replication_radius),
         );
-        let (x, y) = limit_to_world((x, y));
+        let (x, y) = limit_to_world((x, y), &config);
+        if is_blocked(vec2(x, y), &obstacles_query) {
+            continue;
+        }
 
         if consumable.is_food() && is_populate_food {
-            commands.spawn(FoodBundle::new((x, y), handle.0.clone().unwrap()));
+            commands.spawn(FoodBundle::new(
+                (x, y),
+                handle.atlas(),
+                clock.tick,
+                &config,
+                &biomes,
+            ));
         } else if rng.gen_range(0.0..1.0) > 0.4 && is_populate_poison {
-            commands.spawn(PoisonBundle::new((x, y), handle.0.clone().unwrap()));
+            commands.spawn(PoisonBundle::new(
+                (x, y),
+                handle.atlas(),
+                clock.tick,
+                &config,
+            ));
         }
-        last_replication_ts.0 = Instant::now();
+        last_replication_ts.0 = clock.tick;
     }
 }
 
@@ -146,20 +186,20 @@ impl Consumable {
         (self.is_food() && self.0 <= 0.1) || (!self.is_food() && self.0 >= -0.1)
     }
 
-    fn decay(&mut self) {
+    fn decay(&mut self, config: &SimConfig, season: &SeasonParams) {
         if self.is_food() {
-            self.0 = (self.0 + FOOD_DECAY_RATE).max(0.1);
+            self.0 = (self.0 + config.food_decay_rate * season.food_decay).max(0.1);
             return;
         }
 
-        self.0 = (self.0 + POISON_DECAY_RATE).min(-0.1);
+        self.0 = (self.0 + config.poison_decay_rate).min(-0.1);
     }
 
-    fn opacity(&self) -> f32 {
+    fn opacity(&self, config: &SimConfig) -> f32 {
         if self.is_food() {
-            self.0 / FOOD_NUTRITION
+            self.0 / config.food_nutrition
         } else {
-            self.0 / POISON_DAMAGE
+            self.0 / config.poison_damage
         }
     }
 }
@@ -170,6 +210,7 @@ impl ConsumableBundle {
         handle: Handle<TextureAtlas>,
         color: Color,
         nutrition: f32,
+        spawn_tick: u64,
     ) -> Self {
         Self {
             sprite_sheet_bundle: SpriteSheetBundle {
@@ -184,19 +225,44 @@ impl ConsumableBundle {
                 ..default()
             },
             consumable: Consumable(nutrition),
-            last_replication_ts: LastReplicationTs(Instant::now()),
+            last_replication_ts: LastReplicationTs(spawn_tick),
         }
     }
 }
 
 impl FoodBundle {
-    pub fn new((x, y): (f32, f32), handle: Handle<TextureAtlas>) -> Self {
+    /// Food with the nutrition of the biome it grows in
+    pub fn new(
+        (x, y): (f32, f32),
+        handle: Handle<TextureAtlas>,
+        spawn_tick: u64,
+        config: &SimConfig,
+        biomes: &BiomeMap,
+    ) -> Self {
         Self {
             consumable_bundle: ConsumableBundle::new(
                 (x, y),
                 handle,
-                get_color(COLOR_FOOD),
-                FOOD_NUTRITION,
+                get_color(config.color_food),
+                config.food_nutrition * biomes.params(vec2(x, y), config).nutrition,
+                spawn_tick,
+            ),
+            food: Food,
+        }
+    }
+
+    pub fn restore(
+        snapshot: &ConsumableSnapshot,
+        handle: Handle<TextureAtlas>,
+        config: &SimConfig,
+    ) -> Self {
+        Self {
+            consumable_bundle: ConsumableBundle::new(
+                snapshot.pos,
+                handle,
+                get_color(config.color_food),
+                snapshot.nutrition,
+                snapshot.last_replication_tick,
             ),
             food: Food,
         }
@@ -204,13 +270,36 @@ impl FoodBundle {
 }
 
 impl PoisonBundle {
-    pub fn new((x, y): (f32, f32), handle: Handle<TextureAtlas>) -> Self {
+    pub fn new(
+        (x, y): (f32, f32),
+        handle: Handle<TextureAtlas>,
+        spawn_tick: u64,
+        config: &SimConfig,
+    ) -> Self {
         Self {
             consumable_bundle: ConsumableBundle::new(
                 (x, y),
                 handle,
-                get_color(COLOR_POISON),
-                POISON_DAMAGE,
+                get_color(config.color_poison),
+                config.poison_damage,
+                spawn_tick,
+            ),
+            poison: Poison,
+        }
+    }
+
+    pub fn restore(
+        snapshot: &ConsumableSnapshot,
+        handle: Handle<TextureAtlas>,
+        config: &SimConfig,
+    ) -> Self {
+        Self {
+            consumable_bundle: ConsumableBundle::new(
+                snapshot.pos,
+                handle,
+                get_color(config.color_poison),
+                snapshot.nutrition,
+                snapshot.last_replication_tick,
             ),
             poison: Poison,
         }
@@ -0,0 +1,222 @@
+use std::collections::BTreeMap;
+
+use bevy::prelude::*;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+
+use crate::utils::get_rand_normal;
+use crate::*;
+
+/// Heritable gene values by name, described by the `GeneSpec`s of the config.
+/// Keyed by name so genomes stay valid when the gene list changes.
+#[derive(Component, Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Genome(pub BTreeMap<String, f32>);
+
+impl Genome {
+    /// Every gene drawn from its initial range
+    pub fn rand(specs: &[GeneSpec], rng: &mut impl Rng) -> Self {
+        Self(
+            specs
+                .iter()
+                .map(|spec| (spec.name.to_string(), rand_gene(spec, rng)))
+                .collect(),
+        )
+    }
+
+    /// Copy with Gaussian mutations, clamped to each gene's bounds.
+    /// Genes no longer in `specs` are dropped and new ones drawn from their initial range.
+    /// How often and how far genes mutate comes from this genome's own mutation genes.
+    pub fn child(&self, specs: &[GeneSpec], config: &SimConfig, rng: &mut impl Rng) -> Self {
+        let parent_rate = self.mutation_rate(config);
+        let scale = self.mutation_scale();
+        let genes = specs
+            .iter()
+            .map(|spec| {
+                let mut value = match self.0.get(spec.name.as_ref()) {
+                    Some(value) => *value,
+                    None => rand_gene(spec, rng),
+                };
+                let mutation_rate = spec.mutation_rate.unwrap_or(parent_rate);
+                if rng.gen_range(0.0..1.0) < mutation_rate {
+                    value += get_rand_normal(rng) * spec.sigma * scale;
+                }
+                (spec.name.to_string(), value.clamp(spec.min, spec.max))
+            })
+            .collect();
+
+        Self(genes)
+    }
+
+    /// Mix of both parents' genes, genes only one of them has are taken as they are
+    pub fn crossover(&self, other: &Genome, crossover: Crossover, rng: &mut impl Rng) -> Self {
+        let mut genes = other.0.clone();
+        for (name, value) in self.0.iter() {
+            let mixed = match other.0.get(name) {
+                None => *value,
+                Some(other_value) => match crossover {
+                    Crossover::Uniform => {
+                        if rng.gen_range(0.0..1.0) > 0.5 {
+                            *value
+                        } else {
+                            *other_value
+                        }
+                    }
+                    Crossover::Blend => value + (other_value - value) * rng.gen_range(0.0..=1.0),
+                },
+            };
+            genes.insert(name.clone(), mixed);
+        }
+
+        Self(genes)
+    }
+
+    /// Mean difference over the genes in `specs`, each as a fraction of its min to max range.
+    /// Fixed genes, whose max isn't above their min, are left out.
+    pub fn distance(&self, other: &Genome, specs: &[GeneSpec]) -> f32 {
+        let differences: Vec<f32> = specs
+            .iter()
+            .filter(|spec| spec.max > spec.min)
+            .map(|spec| {
+                (self.get(&spec.name) - other.get(&spec.name)).abs() / (spec.max - spec.min)
+            })
+            .collect();
+        if differences.is_empty() {
+            return 0.0;
+        }
+        differences.iter().sum::<f32>() / differences.len() as f32
+    }
+
+    /// Gene by gene average of `genomes` over the genes in `specs`
+    pub fn mean(genomes: &[&Genome], specs: &[GeneSpec]) -> Self {
+        let num_genomes = genomes.len().max(1) as f32;
+        Self(
+            specs
+                .iter()
+                .map(|spec| {
+                    let sum: f32 = genomes.iter().map(|g| g.get(&spec.name)).sum();
+                    (spec.name.to_string(), sum / num_genomes)
+                })
+                .collect(),
+        )
+    }
+
+    /// Chance for each gene of a child to mutate, unless its spec sets its own
+    pub fn mutation_rate(&self, config: &SimConfig) -> f32 {
+        self.0
+            .get(MUTATION_RATE_GENE)
+            .copied()
+            .unwrap_or(1.0 - config.boid_mutation_threshold)
+    }
+
+    /// Factor on the mutation sigma of every gene
+    pub fn mutation_scale(&self) -> f32 {
+        self.0.get(MUTATION_SCALE_GENE).copied().unwrap_or(1.0)
+    }
+
+    /// Value of a gene, 0 if the genome doesn't have it
+    pub fn get(&self, name: &str) -> f32 {
+        self.0.get(name).copied().unwrap_or(0.0)
+    }
+}
+
+fn rand_gene(spec: &GeneSpec, rng: &mut impl Rng) -> f32 {
+    rng.gen_range(spec.init.0..=spec.init.1)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::borrow::Cow;
+
+    use rand::SeedableRng;
+    use rand_chacha::ChaCha8Rng;
+
+    use super::*;
+
+    fn spec(name: &'static str, min: f32, max: f32, sigma: f32) -> GeneSpec {
+        GeneSpec {
+            name: Cow::Borrowed(name),
+            min,
+            max,
+            init: (min, max),
+            sigma,
+            mutation_rate: Some(1.0),
+            plot: Cow::Borrowed(name),
+        }
+    }
+
+    fn genome(genes: &[(&str, f32)]) -> Genome {
+        Genome(genes.iter().map(|(n, v)| (n.to_string(), *v)).collect())
+    }
+
+    #[test]
+    fn distance_leaves_out_fixed_genes() {
+        let a = genome(&[("speed", 1.0), ("size", 2.0)]);
+        let b = genome(&[("speed", 3.0), ("size", 2.0)]);
+        let specs = [spec("speed", 0.0, 4.0, 1.0)];
+        assert_eq!(a.distance(&b, &specs), 0.5);
+
+        let with_fixed = [spec("speed", 0.0, 4.0, 1.0), spec("size", 2.0, 2.0, 0.0)];
+        assert_eq!(a.distance(&b, &with_fixed), 0.5);
+        assert_eq!(a.distance(&b, &with_fixed[1..]), 0.0);
+        assert_eq!(a.distance(&b, &[]), 0.0);
+    }
+
+    #[test]
+    fn children_stay_within_bounds() {
+        let mut rng = ChaCha8Rng::seed_from_u64(1);
+        let config = SimConfig::default();
+        let specs = [spec("speed", 1.0, 2.0, 1000.0)];
+        let mut genome = Genome::rand(&specs, &mut rng);
+        let mut values = Vec::new();
+        for _ in 0..100 {
+            genome = genome.child(&specs, &config, &mut rng);
+            let value = genome.get("speed");
+            assert!((1.0..=2.0).contains(&value));
+            values.push(value);
+        }
+        // Mutated every time, most land on a bound
+        assert!(values.contains(&1.0) && values.contains(&2.0));
+    }
+
+    #[test]
+    fn children_follow_the_specs() {
+        let mut rng = ChaCha8Rng::seed_from_u64(2);
+        let config = SimConfig::default();
+        let parent = genome(&[("speed", 1.5), ("dropped", 7.0)]);
+        let mut new = spec("new", 5.0, 6.0, 0.0);
+        new.init = (5.5, 5.5);
+        let specs = [spec("speed", 1.0, 2.0, 0.0), new];
+
+        let child = parent.child(&specs, &config, &mut rng);
+        assert_eq!(child, genome(&[("speed", 1.5), ("new", 5.5)]));
+    }
+
+    #[test]
+    fn no_mutation_without_mutation_rate() {
+        let mut rng = ChaCha8Rng::seed_from_u64(3);
+        let config = SimConfig::default();
+        let mut specs = vec![spec("speed", 0.0, 10.0, 5.0), spec("size", 0.0, 10.0, 5.0)];
+        specs[1].mutation_rate = None;
+        // Specs set their own rate, the others use the genome's mutation_rate gene
+        specs[0].mutation_rate = Some(0.0);
+        let parent = genome(&[("speed", 4.0), ("size", 6.0), (MUTATION_RATE_GENE, 0.0)]);
+        for _ in 0..20 {
+            let child = parent.child(&specs, &config, &mut rng);
+            assert_eq!(child, genome(&[("speed", 4.0), ("size", 6.0)]));
+        }
+    }
+
+    #[test]
+    fn blend_stays_between_parents() {
+        let mut rng = ChaCha8Rng::seed_from_u64(4);
+        let a = genome(&[("speed", 1.0), ("size", 5.0), ("only_a", 3.0)]);
+        let b = genome(&[("speed", 2.0), ("size", -5.0), ("only_b", 4.0)]);
+        for _ in 0..100 {
+            let child = a.crossover(&b, Crossover::Blend, &mut rng);
+            assert!((1.0..=2.0).contains(&child.get("speed")));
+            assert!((-5.0..=5.0).contains(&child.get("size")));
+            assert_eq!(child.get("only_a"), 3.0);
+            assert_eq!(child.get("only_b"), 4.0);
+        }
+    }
+}
@@ -1,8 +1,32 @@
+use std::collections::HashSet;
+
+use bevy::ecs::system::SystemParam;
 use bevy::prelude::*;
+use rand::SeedableRng;
+use rand_chacha::ChaCha8Rng;
+
+use crate::clock::SimClock;
+use crate::configs::SimConfig;
 
 #[derive(Resource)]
 pub struct GlobalTextureHandle(pub Option<Handle<TextureAtlas>>);
 
+/// Single source of randomness for the simulation, seeded once at startup.
+/// Systems drawing from it are ordered through `SimSet` so that the same seed
+/// always produces the same run.
+#[derive(Resource)]
+pub struct SimRng(pub ChaCha8Rng);
+
+/// What systems that spawn agents or consumables share
+#[derive(SystemParam)]
+pub struct Spawner<'w, 's> {
+    pub commands: Commands<'w, 's>,
+    pub clock: Res<'w, SimClock>,
+    pub config: Res<'w, SimConfig>,
+    pub rng: ResMut<'w, SimRng>,
+    pub handle: Res<'w, GlobalTextureHandle>,
+}
+
 #[derive(Debug, Clone, Copy, Default, Eq, PartialEq, Hash, States)]
 pub enum SimState {
     #[default]
@@ -11,6 +35,17 @@ pub enum SimState {
     Simulating,
 }
 
+#[derive(Debug, Clone, PartialEq, Eq, Hash, SystemSet)]
+pub enum SimSet {
+    Clock,
+    World,
+    Scenario,
+    Catastrophe,
+    Boids,
+    Elements,
+    Stats,
+}
+
 #[derive(Resource)]
 pub struct Settings {
     pub camera_follow_boid: bool,
@@ -25,10 +60,24 @@ pub struct Settings {
 pub struct PlotOptions {
     pub num_boids: bool,
     pub lifespan: bool,
-    pub perception: bool,
-    pub affinity: bool,
-    pub steering: bool,
-    pub speed: bool,
+    pub generation: bool,
+    pub species: bool,
+    pub network: bool,
+    /// Gene graphs, by `GeneSpec::plot`, that aren't shown
+    pub hidden_genes: HashSet<String>,
+}
+
+impl GlobalTextureHandle {
+    /// Sprite sheet handle, or a default one when running without a renderer
+    pub fn atlas(&self) -> Handle<TextureAtlas> {
+        self.0.clone().unwrap_or_default()
+    }
+}
+
+impl SimRng {
+    pub fn new(seed: u64) -> Self {
+        Self(ChaCha8Rng::seed_from_u64(seed))
+    }
 }
 
 impl Default for Settings {
@@ -50,10 +99,10 @@ impl Default for PlotOptions {
         Self {
             num_boids: false,
             lifespan: true,
-            perception: true,
-            affinity: true,
-            steering: false,
-            speed: false,
+            generation: true,
+            species: true,
+            network: true,
+            hidden_genes: ["steering", "speed"].map(String::from).into(),
         }
     }
 }
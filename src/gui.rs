@@ -1,18 +1,30 @@
-use bevy::{math::vec2, prelude::*};
+use bevy::{
+    math::vec2,
+    prelude::*,
+    render::render_resource::{Extent3d, TextureDimension, TextureFormat},
+};
 use bevy_egui::{
     egui::{self, epaint, Color32, Ui},
     EguiContexts, EguiPlugin, EguiSettings,
 };
-use egui_plot::{Line, Plot, PlotPoints, PlotResponse};
+use egui_plot::{Legend, Line, Plot, PlotPoints, PlotResponse, VLine};
 
-use crate::boid::{Boid, Dna, Predator, PredatorDna};
+use crate::biome::BiomeMap;
+use crate::boid::{Dna, Predator, PredatorDna, PreyFilter};
+use crate::obstacles::Obstacle;
+use crate::season::SeasonCycle;
 use crate::stats::*;
 use crate::utils::{get_color, get_color32};
 use crate::*;
 
 pub struct GuiPlugin;
 
+/// Background sprite showing the biome map
+#[derive(Component)]
+struct BiomeLayer;
+
 struct PlotData<'a> {
+    name: &'a str,
     points: &'a Vec<f32>,
     color: Color32,
 }
@@ -28,7 +40,12 @@ impl Plugin for GuiPlugin {
                     draw_boid_debug_gizmos,
                     draw_predator_debug_gizmos,
                     draw_world_boundary,
+                    draw_obstacles,
                     show_plot_settings,
+                    show_season,
+                    draw_biome_layer.run_if(
+                        resource_changed::<BiomeMap>().or_else(resource_changed::<SimConfig>()),
+                    ),
                 )
                     .run_if(in_state(SimState::Simulating)),
             );
@@ -39,19 +56,90 @@ fn setup_egui(mut egui_settings: ResMut<EguiSettings>) {
     egui_settings.scale_factor = 1.5;
 }
 
-fn draw_world_boundary(mut gizmos: Gizmos) {
+fn draw_world_boundary(mut gizmos: Gizmos, config: Res<SimConfig>) {
     gizmos.rect_2d(
         Vec2::ZERO,
         0.0,
-        vec2(WORLD_W * 2.0, WORLD_H * 2.0),
+        vec2(config.world_w * 2.0, config.world_h * 2.0),
         Color::GRAY,
     );
 }
 
+/// Replaces the background sprite with one pixel per biome cell, stretched over the world
+fn draw_biome_layer(
+    mut commands: Commands,
+    config: Res<SimConfig>,
+    biomes: Res<BiomeMap>,
+    mut images: ResMut<Assets<Image>>,
+    layer_query: Query<(Entity, &Handle<Image>), With<BiomeLayer>>,
+) {
+    for (e, image) in layer_query.iter() {
+        images.remove(image);
+        commands.entity(e).despawn();
+    }
+    // A uniform map is left to the clear color
+    if biomes.is_uniform() {
+        return;
+    }
+
+    let (cols, rows) = biomes.size();
+    // Texture rows go top to bottom, biome rows bottom to top
+    let data = biomes
+        .cells()
+        .chunks_exact(cols)
+        .rev()
+        .flatten()
+        .flat_map(|biome| {
+            let (r, g, b) = biome.params(&config).color;
+            [r, g, b, 255]
+        })
+        .collect();
+    let image = Image::new(
+        Extent3d {
+            width: cols as u32,
+            height: rows as u32,
+            depth_or_array_layers: 1,
+        },
+        TextureDimension::D2,
+        data,
+        TextureFormat::Rgba8UnormSrgb,
+    );
+
+    commands.spawn((
+        SpriteBundle {
+            texture: images.add(image),
+            sprite: Sprite {
+                custom_size: Some(vec2(config.world_w * 2.0, config.world_h * 2.0)),
+                ..default()
+            },
+            ..default()
+        },
+        BiomeLayer,
+    ));
+}
+
+fn draw_obstacles(mut gizmos: Gizmos, obstacles_query: Query<&Obstacle>) {
+    for obstacle in obstacles_query.iter() {
+        match obstacle.0 {
+            ObstacleShape::Circle { center, radius } => {
+                gizmos.circle_2d(center.into(), radius, Color::GRAY);
+            }
+            ObstacleShape::Rect {
+                center,
+                size,
+                angle,
+            } => {
+                gizmos.rect_2d(center.into(), angle.to_radians(), size.into(), Color::GRAY);
+            }
+        }
+    }
+}
+
 fn draw_boid_debug_gizmos(
     mut gizmos: Gizmos,
     settings: Res<Settings>,
-    boids_query: Query<(&Transform, &Dna), (With<Boid>, Without<Predator>)>,
+    config: Res<SimConfig>,
+    boids_query: Query<(&Transform, &Dna), PreyFilter>,
 ) {
     if !settings.enable_gizmos {
         return;
@@ -74,7 +162,7 @@ fn draw_boid_debug_gizmos(
         gizmos.circle_2d(
             transform.translation.truncate(),
             dna.predator_perception_radius,
-            get_color(COLOR_PREDATOR),
+            get_color(config.color_predator),
         );
     }
 }
@@ -100,7 +188,11 @@ fn draw_predator_debug_gizmos(
     }
 }
 
-fn show_plot_settings(mut contexts: EguiContexts, mut settings: ResMut<Settings>) {
+fn show_plot_settings(
+    mut contexts: EguiContexts,
+    mut settings: ResMut<Settings>,
+    config: Res<SimConfig>,
+) {
     if !settings.show_plot_settings {
         return;
     }
@@ -111,18 +203,54 @@ fn show_plot_settings(mut contexts: EguiContexts, mut settings: ResMut<Settings>
             .show(ui, |ui| {
                 ui.checkbox(&mut settings.plot_options.num_boids, "Number of Boids");
                 ui.checkbox(&mut settings.plot_options.lifespan, "Lifespan");
-                ui.checkbox(&mut settings.plot_options.perception, "Perception");
-                ui.checkbox(&mut settings.plot_options.affinity, "Affinity");
-                ui.checkbox(&mut settings.plot_options.steering, "Steering");
-                ui.checkbox(&mut settings.plot_options.speed, "Speed");
+                ui.checkbox(&mut settings.plot_options.generation, "Generation");
+                ui.checkbox(&mut settings.plot_options.species, "Species");
+                ui.checkbox(&mut settings.plot_options.network, "Network");
+                let hidden = &mut settings.plot_options.hidden_genes;
+                for plot in gene_plots(&config) {
+                    let mut is_shown = !hidden.contains(plot);
+                    if ui.checkbox(&mut is_shown, plot_title(plot)).changed() {
+                        if is_shown {
+                            hidden.remove(plot);
+                        } else {
+                            hidden.insert(plot.to_string());
+                        }
+                    }
+                }
             });
     });
 }
 
+/// Current season and time of day, top center
+fn show_season(mut contexts: EguiContexts, config: Res<SimConfig>, cycle: Res<SeasonCycle>) {
+    let mut parts = Vec::new();
+    if let Some(season) = cycle.season {
+        parts.push(season.to_string());
+    }
+    if config.day_length > 0.0 {
+        let time_of_day = if cycle.is_night { "Night" } else { "Day" };
+        parts.push(format!("{} {}", time_of_day, cycle.day + 1));
+    }
+    if parts.is_empty() {
+        return;
+    }
+
+    egui::Area::new("season")
+        .anchor(egui::Align2::CENTER_TOP, egui::vec2(0.0, 10.0))
+        .show(contexts.ctx_mut(), |ui| {
+            ui.label(
+                egui::RichText::new(parts.join(" - "))
+                    .color(Color32::WHITE)
+                    .strong(),
+            );
+        });
+}
+
 fn update_egui_plots(
     mut contexts: EguiContexts,
     stats: Res<SimulationStats>,
     settings: Res<Settings>,
+    config: Res<SimConfig>,
 ) {
     if !settings.show_plots {
         return;
@@ -144,6 +272,7 @@ fn update_egui_plots(
         ..old
     });
 
+    let markers = stats.marker_positions();
     egui::Window::new("")
         .title_bar(false)
         .default_pos(egui::pos2(1500.0, 0.0))
@@ -154,22 +283,27 @@ fn update_egui_plots(
                     "Number of Boids",
                     vec![
                         PlotData {
+                            name: "poison",
                             color: Color32::RED,
                             points: &stats.num_poison.items,
                         },
                         PlotData {
+                            name: "food",
                             color: Color32::GREEN,
                             points: &stats.num_food.items,
                         },
                         PlotData {
-                            color: get_color32(COLOR_PREDATOR_LOW_HEALTH),
+                            name: "predators",
+                            color: get_color32(config.color_predator_low_health),
                             points: &stats.num_predators.items,
                         },
                         PlotData {
+                            name: "boids",
                             color: Color32::WHITE,
                             points: &stats.num_boids.items,
                         },
                     ],
+                    &markers,
                     ui,
                 );
             }
@@ -178,97 +312,163 @@ fn update_egui_plots(
                     "Lifespan",
                     vec![
                         PlotData {
+                            name: "boids",
                             color: Color32::WHITE,
                             points: &stats.avg_lifespan.items,
                         },
                         PlotData {
-                            color: get_color32(COLOR_PREDATOR_LOW_HEALTH),
+                            name: "predators",
+                            color: get_color32(config.color_predator_low_health),
                             points: &stats.avg_predator_lifespan.items,
                         },
                     ],
+                    &markers,
                     ui,
                 );
             }
-            if settings.plot_options.perception {
+            if settings.plot_options.generation {
                 get_plot(
-                    "Perception",
+                    "Generation",
                     vec![
                         PlotData {
-                            color: Color32::GREEN,
-                            points: &stats.food_perception.items,
+                            name: "boids",
+                            color: Color32::WHITE,
+                            points: &stats.avg_generation.items,
                         },
                         PlotData {
-                            color: Color32::RED,
-                            points: &stats.poison_perception.items,
+                            name: "boids max",
+                            color: Color32::GRAY,
+                            points: &stats.max_generation.items,
                         },
                         PlotData {
-                            color: get_color32(COLOR_PREDATOR),
-                            points: &stats.predator_perception.items,
+                            name: "predators",
+                            color: get_color32(config.color_predator_low_health),
+                            points: &stats.avg_predator_generation.items,
                         },
                         PlotData {
-                            color: get_color32(COLOR_BOID_LOW_HEALTH),
-                            points: &stats.prey_perception.items,
+                            name: "predators max",
+                            color: get_color32(config.color_predator),
+                            points: &stats.max_predator_generation.items,
                         },
                     ],
+                    &markers,
                     ui,
                 );
             }
-            if settings.plot_options.affinity {
+            if settings.plot_options.species {
                 get_plot(
-                    "Affinity",
+                    "Species",
                     vec![
                         PlotData {
-                            color: Color32::GREEN,
-                            points: &stats.food_affinity.items,
-                        },
-                        PlotData {
-                            color: Color32::RED,
-                            points: &stats.poison_affinity.items,
-                        },
-                        PlotData {
-                            color: get_color32(COLOR_BOID_LOW_HEALTH),
-                            points: &stats.prey_affinity.items,
+                            name: "boids",
+                            color: Color32::WHITE,
+                            points: &stats.num_species.items,
                         },
                         PlotData {
-                            color: get_color32(COLOR_PREDATOR_LOW_HEALTH),
-                            points: &stats.predator_affinity.items,
+                            name: "predators",
+                            color: get_color32(config.color_predator_low_health),
+                            points: &stats.num_predator_species.items,
                         },
                     ],
+                    &markers,
                     ui,
                 );
             }
-            if settings.plot_options.steering {
-                get_plot(
-                    "Steering Force",
-                    vec![PlotData {
-                        color: Color32::WHITE,
-                        points: &stats.steering_force.items,
-                    }],
-                    ui,
-                );
-            }
-            if settings.plot_options.speed {
+            if settings.plot_options.network {
                 get_plot(
-                    "Speed",
+                    "Network",
                     vec![
                         PlotData {
+                            name: "boids hidden",
                             color: Color32::WHITE,
-                            points: &stats.speed.items,
+                            points: &stats.avg_hidden_nodes.items,
+                        },
+                        PlotData {
+                            name: "boids connections",
+                            color: Color32::GRAY,
+                            points: &stats.avg_connections.items,
+                        },
+                        PlotData {
+                            name: "predators hidden",
+                            color: get_color32(config.color_predator_low_health),
+                            points: &stats.avg_predator_hidden_nodes.items,
                         },
                         PlotData {
-                            color: get_color32(COLOR_PREDATOR_LOW_HEALTH),
-                            points: &stats.predator_speed.items,
+                            name: "predators connections",
+                            color: get_color32(config.color_predator),
+                            points: &stats.avg_predator_connections.items,
                         },
                     ],
+                    &markers,
                     ui,
                 );
             }
+            for plot in gene_plots(&config) {
+                if settings.plot_options.hidden_genes.contains(plot) {
+                    continue;
+                }
+
+                let mut plots_data = Vec::new();
+                let prey_colors = [
+                    Color32::GREEN,
+                    Color32::RED,
+                    get_color32(config.color_predator),
+                    Color32::LIGHT_BLUE,
+                    Color32::WHITE,
+                ];
+                let predator_colors = [
+                    get_color32(config.color_predator_low_health),
+                    get_color32(config.color_boid_low_health),
+                    Color32::GOLD,
+                ];
+                for (genes, colors, is_predator) in [
+                    (&config.boid_genes, &prey_colors[..], false),
+                    (&config.predator_genes, &predator_colors[..], true),
+                ] {
+                    let specs = genes.iter().filter(|spec| spec.plot == plot);
+                    for (i, spec) in specs.enumerate() {
+                        let name = gene_series_name(spec, is_predator);
+                        let Some((name, series)) = stats.genes.iter().find(|(n, _)| *n == name)
+                        else {
+                            continue;
+                        };
+                        plots_data.push(PlotData {
+                            name,
+                            points: &series.items,
+                            color: colors[i % colors.len()],
+                        });
+                    }
+                }
+                get_plot(&plot_title(plot), plots_data, &markers, ui);
+            }
         });
 }
 
+/// Distinct gene graphs in config order
+fn gene_plots(config: &SimConfig) -> Vec<&str> {
+    let mut plots: Vec<&str> = Vec::new();
+    for spec in config.boid_genes.iter().chain(config.predator_genes.iter()) {
+        if !plots.contains(&spec.plot.as_ref()) {
+            plots.push(&spec.plot);
+        }
+    }
+    plots
+}
+
+/// `food_perception` -> `Food perception`
+fn plot_title(plot: &str) -> String {
+    let title = plot.replace('_', " ");
+    let mut chars = title.chars();
+    match chars.next() {
+        Some(c) => c.to_uppercase().chain(chars).collect(),
+        None => title,
+    }
+}
+
 fn get_plot(
     title: &str,
     plots_data: Vec<PlotData>,
+    markers: &[f64],
     ui: &mut Ui,
 ) -> egui::CollapsingResponse<PlotResponse<()>> {
     let mut lines = Vec::new();
@@ -277,7 +477,10 @@ fn get_plot(
         let curve: PlotPoints = (0..plot.points.len())
             .map(|i| [i as f64, plot.points[i] as f64])
             .collect();
-        let line = Line::new(curve).width(PLOT_LINE_WIDTH).color(plot.color);
+        let line = Line::new(curve)
+            .width(PLOT_LINE_WIDTH)
+            .color(plot.color)
+            .name(plot.name);
         lines.push(line);
     }
 
@@ -289,12 +492,16 @@ fn get_plot(
                 .show_background(false)
                 .show_grid(false)
                 .view_aspect(2.0)
+                .legend(Legend::default())
                 .auto_bounds_x()
                 .auto_bounds_y()
                 .show(ui, |plot_ui| {
                     for l in lines {
                         plot_ui.line(l)
                     }
+                    for x in markers {
+                        plot_ui.vline(VLine::new(*x).width(1.0).color(Color32::GRAY));
+                    }
                 })
         })
 }
@@ -0,0 +1,174 @@
+use std::path::PathBuf;
+use std::time::Duration;
+
+use bevy::app::AppExit;
+use bevy::prelude::*;
+use bevy::time::TimeUpdateStrategy;
+
+use crate::clock::SimClock;
+use crate::lineage::{export_tree, ExportTree};
+use crate::snapshot::{save_snapshot, SaveSnapshot};
+use crate::stats::SimulationStats;
+use crate::*;
+
+/// Runs the simulation without a window or renderer for a fixed number of ticks,
+/// then writes the collected stats, and optionally a world snapshot and ancestry tree, and exits.
+/// Meant to be used on top of `MinimalPlugins`.
+pub struct HeadlessPlugin {
+    pub num_ticks: u64,
+    pub stats_path: PathBuf,
+    pub snapshot_path: Option<PathBuf>,
+    pub tree_path: Option<PathBuf>,
+}
+
+#[derive(Resource)]
+struct HeadlessRun {
+    num_ticks: u64,
+    stats_path: PathBuf,
+    snapshot_path: Option<PathBuf>,
+    tree_path: Option<PathBuf>,
+    /// Tick the simulation started on, later than 0 when a snapshot was restored
+    first_tick: u64,
+    /// Whether the current fixed tick is within `num_ticks`, decided before it starts
+    is_running: bool,
+}
+
+impl HeadlessRun {
+    fn end_tick(&self) -> u64 {
+        self.first_tick + self.num_ticks
+    }
+}
+
+impl Plugin for HeadlessPlugin {
+    fn build(&self, app: &mut App) {
+        // Every frame advances time by exactly one simulation tick, no waiting on the wall clock
+        app.insert_resource(TimeUpdateStrategy::ManualDuration(Duration::from_secs_f64(
+            1.0 / SIM_TICK_RATE as f64,
+        )))
+        .insert_resource(GlobalTextureHandle(None))
+        .insert_resource(HeadlessRun {
+            num_ticks: self.num_ticks,
+            stats_path: self.stats_path.clone(),
+            snapshot_path: self.snapshot_path.clone(),
+            tree_path: self.tree_path.clone(),
+            first_tick: 0,
+            is_running: true,
+        })
+        .add_systems(OnEnter(SimState::Loading), setup)
+        .add_systems(OnEnter(SimState::Simulating), record_first_tick)
+        // Several ticks can run in one frame when `sim_speed` is above 1,
+        // those past `num_ticks` are skipped so the run ends on that exact tick
+        .add_systems(FixedUpdate, start_tick.before(SimSet::Clock))
+        .add_systems(
+            Last,
+            finish_run
+                .before(save_snapshot)
+                .before(export_tree)
+                .run_if(in_state(SimState::Simulating)),
+        );
+        for set in [
+            SimSet::Clock,
+            SimSet::World,
+            SimSet::Scenario,
+            SimSet::Catastrophe,
+            SimSet::Boids,
+            SimSet::Elements,
+            SimSet::Stats,
+        ] {
+            app.configure_sets(FixedUpdate, set.run_if(is_running));
+        }
+    }
+}
+
+fn setup(mut next_state: ResMut<NextState<SimState>>) {
+    next_state.set(SimState::InitSim);
+}
+
+fn record_first_tick(mut run: ResMut<HeadlessRun>, clock: Res<SimClock>) {
+    run.first_tick = clock.tick;
+}
+
+fn start_tick(mut run: ResMut<HeadlessRun>, clock: Res<SimClock>) {
+    run.is_running = clock.tick < run.end_tick();
+}
+
+fn is_running(run: Res<HeadlessRun>) -> bool {
+    run.is_running
+}
+
+fn finish_run(
+    run: Res<HeadlessRun>,
+    clock: Res<SimClock>,
+    stats: Res<SimulationStats>,
+    mut exit: EventWriter<AppExit>,
+    mut snapshot_events: EventWriter<SaveSnapshot>,
+    mut tree_events: EventWriter<ExportTree>,
+) {
+    if clock.tick < run.end_tick() {
+        return;
+    }
+
+    if !stats.markers.is_empty() {
+        let markers_path = run.stats_path.with_extension("markers.csv");
+        if let Err(e) = stats.write_markers_csv(&markers_path) {
+            error!(
+                "Failed to write markers to {}: {}",
+                markers_path.display(),
+                e
+            );
+        }
+    }
+    match stats.write_csv(&run.stats_path) {
+        Ok(()) => info!(
+            "Wrote stats for {} ticks to {}",
+            run.num_ticks,
+            run.stats_path.display()
+        ),
+        Err(e) => error!(
+            "Failed to write stats to {}: {}",
+            run.stats_path.display(),
+            e
+        ),
+    }
+    if let Some(path) = run.snapshot_path.as_ref() {
+        snapshot_events.send(SaveSnapshot(Some(path.clone())));
+    }
+    if let Some(path) = run.tree_path.as_ref() {
+        tree_events.send(ExportTree(Some(path.clone())));
+    }
+    exit.send(AppExit);
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::ecs::system::RunSystemOnce;
+
+    use super::*;
+
+    #[test]
+    fn restored_clock_runs_num_ticks() {
+        let mut world = World::new();
+        world.insert_resource(SimClock { tick: 500 });
+        world.insert_resource(HeadlessRun {
+            num_ticks: 100,
+            stats_path: PathBuf::new(),
+            snapshot_path: None,
+            tree_path: None,
+            first_tick: 0,
+            is_running: true,
+        });
+        world.run_system_once(record_first_tick);
+
+        let mut num_ticks = 0;
+        loop {
+            world.run_system_once(start_tick);
+            if !world.resource::<HeadlessRun>().is_running {
+                break;
+            }
+            world.resource_mut::<SimClock>().tick += 1;
+            num_ticks += 1;
+        }
+        assert_eq!(num_ticks, 100);
+        assert_eq!(world.resource::<SimClock>().tick, 600);
+    }
+}
@@ -1,8 +1,24 @@
+pub mod biome;
 pub mod boid;
+pub mod brain;
+pub mod catastrophe;
+pub mod cli;
+pub mod clock;
+pub mod config_reload;
 pub mod configs;
 pub mod elements;
+pub mod genome;
 pub mod globals;
 pub mod gui;
+pub mod headless;
+pub mod lineage;
+pub mod neat;
+pub mod obstacles;
+pub mod scenario;
+pub mod season;
+pub mod snapshot;
+pub mod spatial;
+pub mod species;
 pub mod stats;
 pub mod utils;
 pub mod world;
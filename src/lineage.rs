@@ -0,0 +1,411 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::fmt::Write as _;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::clock::SimClock;
+use crate::genome::Genome;
+use crate::*;
+
+pub struct LineagePlugin;
+
+/// Where an agent sits in the ancestry, ids grow with every birth
+#[derive(Component, Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Lineage {
+    pub id: u64,
+    /// Parent that replicated, `None` for founders spawned from nothing
+    pub parent: Option<u64>,
+    /// Second parent in sexual reproduction
+    pub mate: Option<u64>,
+    /// 0 for founders, one more than the older parent otherwise
+    pub generation: u32,
+}
+
+/// Ancestry tree of the living agents, following `Lineage::parent`.
+/// Dead agents are kept as long as they have living descendants.
+#[derive(Resource, Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Ancestry {
+    next_id: u64,
+    pub records: BTreeMap<u64, AncestryRecord>,
+    /// Ids of the agents still alive
+    alive: BTreeSet<u64>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AncestryRecord {
+    pub lineage: Lineage,
+    pub is_predator: bool,
+    pub genome: Genome,
+    pub birth_tick: u64,
+    pub death_tick: Option<u64>,
+    /// Children still in the tree
+    num_children: u32,
+}
+
+/// Writes the ancestry trees as Newick with NHX annotations to the given path,
+/// or to `TREE_DIR` when none is given
+#[derive(Event)]
+pub struct ExportTree(pub Option<PathBuf>);
+
+/// Newick subtree waiting for its parent, which may still stretch its branch
+struct Subtree {
+    /// Children and name
+    head: String,
+    branch_ticks: u64,
+    nhx: String,
+}
+
+impl Plugin for LineagePlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<Ancestry>()
+            .add_event::<ExportTree>()
+            .add_systems(
+                FixedUpdate,
+                record_deaths
+                    .in_set(SimSet::World)
+                    .run_if(in_state(SimState::Simulating)),
+            )
+            .add_systems(Last, export_tree.run_if(in_state(SimState::Simulating)));
+    }
+}
+
+/// Marks agents that are no longer around as dead and prunes lineages that died out.
+/// Runs before anything spawns this tick, so the despawns it sees were applied
+/// at the end of the previous one.
+pub fn record_deaths(
+    clock: Res<SimClock>,
+    mut ancestry: ResMut<Ancestry>,
+    lineage_query: Query<&Lineage>,
+) {
+    if ancestry.alive.len() == lineage_query.iter().len() {
+        return;
+    }
+
+    let living: HashSet<u64> = lineage_query.iter().map(|l| l.id).collect();
+    let dead: Vec<u64> = ancestry
+        .alive
+        .iter()
+        .filter(|id| !living.contains(id))
+        .copied()
+        .collect();
+    let death_tick = clock.tick.saturating_sub(1);
+    for id in dead {
+        ancestry.alive.remove(&id);
+        if let Some(record) = ancestry.records.get_mut(&id) {
+            record.death_tick = Some(death_tick);
+        }
+        ancestry.prune(id);
+    }
+}
+
+pub fn export_tree(
+    clock: Res<SimClock>,
+    ancestry: Res<Ancestry>,
+    mut events: EventReader<ExportTree>,
+) {
+    for e in events.read() {
+        let path =
+            e.0.clone()
+                .unwrap_or_else(|| Path::new(TREE_DIR).join(format!("tree_{}.nhx", clock.tick)));
+
+        match ancestry.write_nhx(&path) {
+            Ok(()) => info!(
+                "Wrote the ancestry tree at tick {} to {}",
+                clock.tick,
+                path.display()
+            ),
+            Err(e) => error!(
+                "Failed to write the ancestry tree to {}: {}",
+                path.display(),
+                e
+            ),
+        }
+    }
+}
+
+impl Ancestry {
+    /// Registers an agent without parents
+    pub fn founder(&mut self, genome: &Genome, is_predator: bool, birth_tick: u64) -> Lineage {
+        self.register(None, None, 0, genome, is_predator, birth_tick)
+    }
+
+    /// Registers the child of `parent`, and of `mate` when it had two parents
+    pub fn offspring(
+        &mut self,
+        parent: &Lineage,
+        mate: Option<&Lineage>,
+        genome: &Genome,
+        is_predator: bool,
+        birth_tick: u64,
+    ) -> Lineage {
+        let generation = parent.generation.max(mate.map_or(0, |m| m.generation)) + 1;
+        if let Some(record) = self.records.get_mut(&parent.id) {
+            record.num_children += 1;
+        }
+        self.register(
+            Some(parent.id),
+            mate.map(|m| m.id),
+            generation,
+            genome,
+            is_predator,
+            birth_tick,
+        )
+    }
+
+    pub fn get(&self, id: u64) -> Option<&AncestryRecord> {
+        self.records.get(&id)
+    }
+
+    /// Newick tree of the living prey or predators with NHX annotations, `None` if there are none.
+    /// Each agent is a node at its birth, so branch lengths are the sim-seconds between the births
+    /// of parent and child. Founders hang off a root at tick 0, agents that are still alive and
+    /// have children are internal nodes. Dead agents with a single child in the tree are left out,
+    /// their branch added to the child's.
+    pub fn newick(&self, is_predator: bool) -> Option<String> {
+        let mut children: HashMap<u64, Vec<u64>> = HashMap::new();
+        let mut founders = Vec::new();
+        for (id, record) in self.records.iter() {
+            if record.is_predator != is_predator {
+                continue;
+            }
+            match record.lineage.parent {
+                Some(parent) => children.entry(parent).or_default().push(*id),
+                None => founders.push(*id),
+            }
+        }
+        if founders.is_empty() {
+            return None;
+        }
+
+        // Children are born after their parents, so going from the newest id back
+        // every child's subtree is done before its parent's
+        let mut subtrees: HashMap<u64, Subtree> = HashMap::new();
+        for (id, record) in self.records.iter().rev() {
+            if record.is_predator != is_predator {
+                continue;
+            }
+            let parent_tick = record
+                .lineage
+                .parent
+                .and_then(|p| self.get(p))
+                .map_or(0, |p| p.birth_tick);
+            let branch_ticks = record.birth_tick.saturating_sub(parent_tick);
+            let mut inner: Vec<Subtree> = children
+                .get(id)
+                .map(|ids| ids.iter().filter_map(|c| subtrees.remove(c)).collect())
+                .unwrap_or_default();
+
+            let subtree = if record.death_tick.is_some() && inner.len() == 1 {
+                let mut child = inner.pop().unwrap();
+                child.branch_ticks += branch_ticks;
+                child
+            } else {
+                let (name, nhx) = self.node_label(record);
+                let head = if inner.is_empty() {
+                    name
+                } else {
+                    let inner: Vec<String> = inner.iter().map(Subtree::to_newick).collect();
+                    format!("({}){}", inner.join(","), name)
+                };
+                Subtree {
+                    head,
+                    branch_ticks,
+                    nhx,
+                }
+            };
+            subtrees.insert(*id, subtree);
+        }
+
+        let inner: Vec<String> = founders
+            .iter()
+            .filter_map(|f| subtrees.remove(f))
+            .map(|s| s.to_newick())
+            .collect();
+        let root = if is_predator { "predators" } else { "boids" };
+        Some(format!("({}){};", inner.join(","), root))
+    }
+
+    /// Prey tree then predator tree, one per line
+    pub fn write_nhx(&self, path: &Path) -> io::Result<()> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let trees: Vec<String> = [false, true]
+            .into_iter()
+            .filter_map(|is_predator| self.newick(is_predator))
+            .collect();
+
+        fs::write(path, trees.join("\n") + "\n")
+    }
+
+    /// Name and NHX comment of a node, `b12` and `[&&NHX:...]`, `p` for predators
+    fn node_label(&self, record: &AncestryRecord) -> (String, String) {
+        let prefix = if record.is_predator { "p" } else { "b" };
+        let mut label = format!(
+            "[&&NHX:alive={}:birth={}:generation={}",
+            record.death_tick.is_none() as u8,
+            record.birth_tick as f64 / SIM_TICK_RATE as f64,
+            record.lineage.generation,
+        );
+        if let Some(mate) = record.lineage.mate {
+            let _ = write!(label, ":mate={}{}", prefix, mate);
+        }
+        for (name, value) in record.genome.0.iter() {
+            let _ = write!(label, ":{}={}", name, value);
+        }
+        label.push(']');
+        (format!("{}{}", prefix, record.lineage.id), label)
+    }
+
+    fn register(
+        &mut self,
+        parent: Option<u64>,
+        mate: Option<u64>,
+        generation: u32,
+        genome: &Genome,
+        is_predator: bool,
+        birth_tick: u64,
+    ) -> Lineage {
+        let lineage = Lineage {
+            id: self.next_id,
+            parent,
+            mate,
+            generation,
+        };
+        self.next_id += 1;
+        self.records.insert(
+            lineage.id,
+            AncestryRecord {
+                lineage,
+                is_predator,
+                genome: genome.clone(),
+                birth_tick,
+                death_tick: None,
+                num_children: 0,
+            },
+        );
+        self.alive.insert(lineage.id);
+        lineage
+    }
+
+    /// Removes `id` if it's dead without children in the tree, then its parent the same way
+    fn prune(&mut self, id: u64) {
+        let mut next = Some(id);
+        while let Some(id) = next {
+            let Some(record) = self.records.get(&id) else {
+                return;
+            };
+            if record.death_tick.is_none() || record.num_children > 0 {
+                return;
+            }
+            next = record.lineage.parent;
+            self.records.remove(&id);
+            if let Some(parent) = next.and_then(|p| self.records.get_mut(&p)) {
+                parent.num_children -= 1;
+            }
+        }
+    }
+}
+
+impl Subtree {
+    fn to_newick(&self) -> String {
+        format!(
+            "{}:{}{}",
+            self.head,
+            self.branch_ticks as f64 / SIM_TICK_RATE as f64,
+            self.nhx
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Ticks in a sim-second
+    const SEC: u64 = SIM_TICK_RATE as u64;
+
+    fn genome() -> Genome {
+        Genome([("speed".to_string(), 2.5)].into_iter().collect())
+    }
+
+    fn kill(ancestry: &mut Ancestry, id: u64, tick: u64) {
+        ancestry.alive.remove(&id);
+        ancestry.records.get_mut(&id).unwrap().death_tick = Some(tick);
+        ancestry.prune(id);
+    }
+
+    #[test]
+    fn newick_of_a_small_ancestry() {
+        let mut ancestry = Ancestry::default();
+        let g = genome();
+        let a = ancestry.founder(&g, false, 0);
+        let b = ancestry.offspring(&a, None, &g, false, SEC);
+        let c = ancestry.offspring(&b, None, &g, false, 2 * SEC);
+        let d = ancestry.offspring(&b, Some(&c), &g, false, 3 * SEC);
+        let e = ancestry.founder(&g, false, 0);
+        let f = ancestry.offspring(&e, None, &g, false, SEC);
+        let leaf = ancestry.offspring(&f, None, &g, false, 2 * SEC);
+
+        // Dead with two children stays, dead with one is merged into it, dead leaves go
+        kill(&mut ancestry, b.id, 4 * SEC);
+        kill(&mut ancestry, e.id, 4 * SEC);
+        kill(&mut ancestry, leaf.id, 4 * SEC);
+        assert!(ancestry.get(leaf.id).is_none());
+
+        let nhx = |alive, birth, generation| {
+            format!(
+                "[&&NHX:alive={}:birth={}:generation={}:speed=2.5]",
+                alive, birth, generation
+            )
+        };
+        let expected = format!(
+            "(((b{}:1{},b{}:2{})b{}:1{})b{}:0{},b{}:1{})boids;",
+            c.id,
+            nhx(1, 2, 2),
+            d.id,
+            nhx(1, 3, 3).replace(":speed", &format!(":mate=b{}:speed", c.id)),
+            b.id,
+            nhx(0, 1, 1),
+            a.id,
+            nhx(1, 0, 0),
+            f.id,
+            nhx(1, 1, 1),
+        );
+        assert_eq!(ancestry.newick(false).unwrap(), expected);
+        assert!(ancestry.newick(true).is_none());
+    }
+
+    #[test]
+    fn merged_branches_add_up() {
+        let mut ancestry = Ancestry::default();
+        let g = genome();
+        let a = ancestry.founder(&g, true, SEC);
+        let b = ancestry.offspring(&a, None, &g, true, 3 * SEC);
+        let c = ancestry.offspring(&b, None, &g, true, 6 * SEC);
+        kill(&mut ancestry, b.id, 7 * SEC);
+        kill(&mut ancestry, a.id, 8 * SEC);
+
+        // Founder branch from tick 0, then a to b, then b to c
+        let newick = ancestry.newick(true).unwrap();
+        assert!(newick.starts_with(&format!("(p{}:6[", c.id)));
+        assert!(newick.ends_with(")predators;"));
+    }
+
+    #[test]
+    fn extinct_lineages_are_pruned() {
+        let mut ancestry = Ancestry::default();
+        let g = genome();
+        let a = ancestry.founder(&g, false, 0);
+        let b = ancestry.offspring(&a, None, &g, false, SEC);
+        kill(&mut ancestry, a.id, 2 * SEC);
+        assert!(ancestry.get(a.id).is_some());
+        kill(&mut ancestry, b.id, 3 * SEC);
+        assert!(ancestry.records.is_empty());
+        assert!(ancestry.newick(false).is_none());
+    }
+}
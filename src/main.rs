@@ -1,51 +1,154 @@
+use std::path::Path;
+
+use bevy::log::LogPlugin;
 use bevy::math::vec2;
 use bevy::prelude::*;
 use bevy::window::close_on_esc;
 use bevy_pancam::{PanCam, PanCamPlugin};
+use clap::Parser;
 
 use ecosim::utils::get_color;
 use ecosim::*;
 use ecosim::{
-    boid::BoidPlugin, elements::ElementsPlugin, gui::GuiPlugin, stats::StatsPlugin,
+    biome::BiomePlugin,
+    boid::BoidPlugin,
+    catastrophe::CatastrophePlugin,
+    cli::Cli,
+    clock::ClockPlugin,
+    config_reload::ConfigReloadPlugin,
+    elements::ElementsPlugin,
+    gui::GuiPlugin,
+    headless::HeadlessPlugin,
+    lineage::{ExportTree, LineagePlugin},
+    neat::NeatPlugin,
+    obstacles::ObstaclePlugin,
+    scenario::{Scenario, ScenarioPlugin},
+    season::SeasonPlugin,
+    snapshot::{list_checkpoints, PendingSnapshot, SaveSnapshot, SnapshotPlugin, WorldSnapshot},
+    spatial::SpatialPlugin,
+    species::SpeciesPlugin,
+    stats::StatsPlugin,
     world::WorldPlugin,
 };
 
 fn main() {
-    App::new()
-        .add_state::<SimState>()
-        .add_plugins(
-            DefaultPlugins
-                .set(ImagePlugin::default_nearest())
-                .set(WindowPlugin {
-                    primary_window: Some(Window {
-                        // mode: bevy::window::WindowMode::Fullscreen,
-                        resolution: (WW as f32, WH as f32).into(),
-                        title: "eco-sim".to_string(),
-                        ..default()
-                    }),
-                    ..default()
-                }),
-        )
-        .add_plugins(PanCamPlugin)
-        .insert_resource(Msaa::Off)
-        .insert_resource(ClearColor(get_color(COLOR_BACKGROUND)))
-        .insert_resource(GlobalTextureHandle(None))
+    let cli = Cli::parse();
+    if cli.config != Path::new(CONFIG_PATH) && !cli.config.exists() {
+        eprintln!("{}: config file not found", cli.config.display());
+        std::process::exit(1);
+    }
+    let mut config = match SimConfig::load(&cli.config) {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("{}: {}", cli.config.display(), e);
+            std::process::exit(1);
+        }
+    };
+    let file_config = config.clone();
+    cli.apply(&mut config);
+
+    let snapshot_path = if cli.resume {
+        let checkpoints = list_checkpoints(Path::new(CHECKPOINT_DIR)).unwrap_or_else(|e| {
+            eprintln!("{}: {}", CHECKPOINT_DIR, e);
+            std::process::exit(1);
+        });
+        if checkpoints.is_empty() {
+            eprintln!(
+                "No checkpoint found in {}, starting a new run",
+                CHECKPOINT_DIR
+            );
+        }
+        checkpoints.last().cloned()
+    } else {
+        cli.snapshot_in.clone()
+    };
+
+    let mut app = App::new();
+    if let Some(path) = snapshot_path.as_ref() {
+        match WorldSnapshot::load(path) {
+            Ok(snapshot) => app.insert_resource(PendingSnapshot(snapshot)),
+            Err(e) => {
+                eprintln!("{}: {}", path.display(), e);
+                std::process::exit(1);
+            }
+        };
+    }
+    if let Some(path) = cli.scenario.as_ref() {
+        match Scenario::load(path, &config) {
+            Ok(scenario) => app.insert_resource(scenario),
+            Err(e) => {
+                eprintln!("{}: {}", path.display(), e);
+                std::process::exit(1);
+            }
+        };
+    }
+    if cli.headless {
+        app.add_plugins(MinimalPlugins)
+            .add_plugins(LogPlugin::default())
+            .add_plugins(HeadlessPlugin {
+                num_ticks: cli.ticks,
+                stats_path: cli.stats_out.clone(),
+                snapshot_path: cli.snapshot_out.clone(),
+                tree_path: cli.tree_out.clone(),
+            });
+    } else {
+        add_gui_plugins(&mut app, &config);
+    }
+
+    app.add_state::<SimState>()
+        .insert_resource(SimRng::new(config.seed))
+        .insert_resource(config)
+        .add_plugins(ConfigReloadPlugin {
+            path: cli.config.clone(),
+            file_config,
+        })
         .insert_resource(Settings::default())
+        .add_plugins(ClockPlugin)
         .add_plugins(BoidPlugin)
-        .add_plugins(GuiPlugin)
         .add_plugins(StatsPlugin)
         .add_plugins(WorldPlugin)
         .add_plugins(ElementsPlugin)
-        .add_systems(OnEnter(SimState::Loading), setup)
-        .add_systems(
-            Update,
-            camera_clamp_system.run_if(in_state(SimState::Simulating)),
-        )
-        .add_systems(Update, handle_keyboard_input)
-        .add_systems(Update, close_on_esc)
+        .add_plugins(SnapshotPlugin)
+        .add_plugins(SpatialPlugin)
+        .add_plugins(ObstaclePlugin)
+        .add_plugins(BiomePlugin)
+        .add_plugins(SeasonPlugin)
+        .add_plugins(ScenarioPlugin)
+        .add_plugins(CatastrophePlugin)
+        .add_plugins(LineagePlugin)
+        .add_plugins(SpeciesPlugin)
+        .add_plugins(NeatPlugin)
         .run();
 }
 
+fn add_gui_plugins(app: &mut App, config: &SimConfig) {
+    app.add_plugins(
+        DefaultPlugins
+            .set(ImagePlugin::default_nearest())
+            .set(WindowPlugin {
+                primary_window: Some(Window {
+                    // mode: bevy::window::WindowMode::Fullscreen,
+                    resolution: (config.window_width as f32, config.window_height as f32).into(),
+                    title: "eco-sim".to_string(),
+                    ..default()
+                }),
+                ..default()
+            }),
+    )
+    .add_plugins(PanCamPlugin)
+    .insert_resource(Msaa::Off)
+    .insert_resource(ClearColor(get_color(config.color_background)))
+    .insert_resource(GlobalTextureHandle(None))
+    .add_plugins(GuiPlugin)
+    .add_systems(OnEnter(SimState::Loading), setup)
+    .add_systems(
+        Update,
+        camera_clamp_system.run_if(in_state(SimState::Simulating)),
+    )
+    .add_systems(Update, handle_keyboard_input)
+    .add_systems(Update, close_on_esc);
+}
+
 fn setup(
     mut commands: Commands,
     mut handle: ResMut<GlobalTextureHandle>,
@@ -85,7 +188,13 @@ fn camera_clamp_system(
     cam_transform.translation = cam_transform.translation.lerp(Vec3::ZERO, 0.05);
 }
 
-fn handle_keyboard_input(keys: Res<Input<KeyCode>>, mut settings: ResMut<Settings>) {
+fn handle_keyboard_input(
+    keys: Res<Input<KeyCode>>,
+    mut settings: ResMut<Settings>,
+    mut time: ResMut<Time<Virtual>>,
+    mut snapshot_events: EventWriter<SaveSnapshot>,
+    mut tree_events: EventWriter<ExportTree>,
+) {
     if keys.just_pressed(KeyCode::Key1) {
         settings.camera_follow_boid = !settings.camera_follow_boid;
         settings.camera_follow_predator = false;
@@ -110,4 +219,18 @@ fn handle_keyboard_input(keys: Res<Input<KeyCode>>, mut settings: ResMut<Setting
     if keys.just_pressed(KeyCode::Grave) {
         settings.show_plot_settings = !settings.show_plot_settings;
     }
+    if keys.just_pressed(KeyCode::BracketRight) {
+        let speed = (time.relative_speed() * 2.0).min(MAX_SIM_SPEED);
+        time.set_relative_speed(speed);
+    }
+    if keys.just_pressed(KeyCode::BracketLeft) {
+        let speed = (time.relative_speed() / 2.0).max(1.0 / MAX_SIM_SPEED);
+        time.set_relative_speed(speed);
+    }
+    if keys.just_pressed(KeyCode::F5) {
+        snapshot_events.send(SaveSnapshot(None));
+    }
+    if keys.just_pressed(KeyCode::F6) {
+        tree_events.send(ExportTree(None));
+    }
 }
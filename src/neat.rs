@@ -0,0 +1,621 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+use bevy::prelude::*;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+
+use crate::brain::{num_inputs, Graph, OUTPUTS};
+use crate::genome::Genome;
+use crate::utils::get_rand_normal;
+use crate::*;
+
+pub struct NeatPlugin;
+
+/// NEAT genome of the network that steers the agent: node genes and connection genes,
+/// both sorted by id. Starts with every input and the bias connected straight to the
+/// outputs and grows hidden nodes and connections by mutation.
+/// Empty unless the `Neat` controller is used.
+#[derive(Component, Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct NeatGenome {
+    pub nodes: Vec<NodeGene>,
+    pub connections: Vec<ConnectionGene>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct NodeGene {
+    pub id: u32,
+    pub kind: NodeKind,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum NodeKind {
+    Input,
+    Bias,
+    Hidden,
+    Output,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct ConnectionGene {
+    /// Same for every connection between the same nodes, so crossover can line genes up
+    pub innovation: u32,
+    pub from: u32,
+    pub to: u32,
+    pub weight: f32,
+    /// Disabled connections were split by a new node, they stay in the genome
+    pub enabled: bool,
+}
+
+/// Innovation numbers and node ids handed out by structural mutations, prey and predators
+/// have their own. Kept for the whole run, so the same mutation in unrelated lineages
+/// gets the same numbers.
+#[derive(Resource, Debug, Clone, Serialize, Deserialize)]
+pub struct Innovations {
+    boids: InnovationTable,
+    predators: InnovationTable,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct InnovationTable {
+    next_innovation: u32,
+    next_node: u32,
+    /// Innovation of each added connection, by the nodes it joins
+    connections: BTreeMap<(u32, u32), u32>,
+    /// Node added by splitting each connection, by the connection's innovation
+    splits: BTreeMap<u32, u32>,
+}
+
+impl Plugin for NeatPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<Innovations>();
+    }
+}
+
+impl Default for Innovations {
+    fn default() -> Self {
+        Self {
+            boids: InnovationTable::new(false),
+            predators: InnovationTable::new(true),
+        }
+    }
+}
+
+impl Innovations {
+    pub fn table(&mut self, is_predator: bool) -> &mut InnovationTable {
+        if is_predator {
+            &mut self.predators
+        } else {
+            &mut self.boids
+        }
+    }
+}
+
+impl InnovationTable {
+    /// Numbers after those of the initial genome, see `NeatGenome::initial`
+    fn new(is_predator: bool) -> Self {
+        let num_sources = num_inputs(is_predator) as u32 + 1;
+        Self {
+            next_innovation: num_sources * OUTPUTS as u32,
+            next_node: num_sources + OUTPUTS as u32,
+            connections: BTreeMap::new(),
+            splits: BTreeMap::new(),
+        }
+    }
+
+    fn connection(&mut self, from: u32, to: u32) -> u32 {
+        *self.connections.entry((from, to)).or_insert_with(|| {
+            self.next_innovation += 1;
+            self.next_innovation - 1
+        })
+    }
+
+    fn split(&mut self, innovation: u32) -> u32 {
+        *self.splits.entry(innovation).or_insert_with(|| {
+            self.next_node += 1;
+            self.next_node - 1
+        })
+    }
+}
+
+impl NeatGenome {
+    /// Inputs first, then the bias and the outputs, with a connection from each input and the bias
+    /// to each output weighted from `brain_weight_gene`'s initial range.
+    /// Draws nothing unless the `Neat` controller is used.
+    pub fn initial(config: &SimConfig, is_predator: bool, rng: &mut impl Rng) -> Self {
+        let Controller::Neat { .. } = config.controller else {
+            return Self::default();
+        };
+        let num_inputs = num_inputs(is_predator) as u32;
+        let num_sources = num_inputs + 1;
+        let mut nodes: Vec<NodeGene> = (0..num_inputs)
+            .map(|id| NodeGene {
+                id,
+                kind: NodeKind::Input,
+            })
+            .collect();
+        nodes.push(NodeGene {
+            id: num_inputs,
+            kind: NodeKind::Bias,
+        });
+        nodes.extend((0..OUTPUTS as u32).map(|o| NodeGene {
+            id: num_sources + o,
+            kind: NodeKind::Output,
+        }));
+
+        let spec = &config.brain_weight_gene;
+        let mut connections = Vec::new();
+        for o in 0..OUTPUTS as u32 {
+            for from in 0..num_sources {
+                connections.push(ConnectionGene {
+                    innovation: o * num_sources + from,
+                    from,
+                    to: num_sources + o,
+                    weight: rng.gen_range(spec.init.0..=spec.init.1),
+                    enabled: true,
+                });
+            }
+        }
+
+        Self { nodes, connections }
+    }
+
+    /// Copy with weight mutations, at the rate and scale of the parent's mutation genes and
+    /// bounded by `brain_weight_gene`, then maybe a new connection and maybe a new node.
+    /// Genomes from before the switch to the `Neat` controller start over from `initial`.
+    pub fn child(
+        &self,
+        genome: &Genome,
+        is_predator: bool,
+        config: &SimConfig,
+        innovations: &mut Innovations,
+        rng: &mut impl Rng,
+    ) -> Self {
+        let Controller::Neat {
+            add_connection,
+            add_node,
+        } = config.controller
+        else {
+            return Self::default();
+        };
+        if self.nodes.is_empty() {
+            return Self::initial(config, is_predator, rng);
+        }
+
+        let spec = &config.brain_weight_gene;
+        let mutation_rate = spec.mutation_rate.unwrap_or(genome.mutation_rate(config));
+        let sigma = spec.sigma * genome.mutation_scale();
+        let mut child = self.clone();
+        for connection in child.connections.iter_mut() {
+            if rng.gen_range(0.0..1.0) < mutation_rate {
+                connection.weight =
+                    (connection.weight + get_rand_normal(rng) * sigma).clamp(spec.min, spec.max);
+            }
+        }
+        let table = innovations.table(is_predator);
+        if rng.gen_range(0.0..1.0) < add_connection {
+            child.add_connection(table, config, rng);
+        }
+        if rng.gen_range(0.0..1.0) < add_node {
+            child.add_node(table, config, rng);
+        }
+
+        child
+    }
+
+    /// Nodes and connections of `self`, connections both parents have take their weight
+    /// from either or a blend of both. NEAT takes the unmatched genes from the fitter parent,
+    /// here that's the one that replicated.
+    pub fn crossover(&self, other: &NeatGenome, crossover: Crossover, rng: &mut impl Rng) -> Self {
+        let mut child = self.clone();
+        for connection in child.connections.iter_mut() {
+            let Ok(i) = other
+                .connections
+                .binary_search_by_key(&connection.innovation, |c| c.innovation)
+            else {
+                continue;
+            };
+            let other_weight = other.connections[i].weight;
+            connection.weight = match crossover {
+                Crossover::Uniform => {
+                    if rng.gen_range(0.0..1.0) > 0.5 {
+                        connection.weight
+                    } else {
+                        other_weight
+                    }
+                }
+                Crossover::Blend => {
+                    connection.weight
+                        + (other_weight - connection.weight) * rng.gen_range(0.0..=1.0)
+                }
+            };
+        }
+
+        child
+    }
+
+    /// Orders the nodes so each comes after every node feeding it
+    pub fn graph(&self) -> Graph {
+        let index: HashMap<u32, usize> = self
+            .nodes
+            .iter()
+            .enumerate()
+            .map(|(i, n)| (n.id, i))
+            .collect();
+        let mut incoming: Vec<Vec<(usize, f32)>> = vec![Vec::new(); self.nodes.len()];
+        let mut outgoing: Vec<Vec<usize>> = vec![Vec::new(); self.nodes.len()];
+        for c in self.connections.iter().filter(|c| c.enabled) {
+            let (Some(&from), Some(&to)) = (index.get(&c.from), index.get(&c.to)) else {
+                continue;
+            };
+            incoming[to].push((from, c.weight));
+            outgoing[from].push(to);
+        }
+
+        let mut num_pending: Vec<usize> = incoming.iter().map(|i| i.len()).collect();
+        let mut ready: BTreeSet<usize> = (0..self.nodes.len())
+            .filter(|i| num_pending[*i] == 0)
+            .collect();
+        let mut steps = Vec::new();
+        while let Some(i) = ready.pop_first() {
+            if matches!(self.nodes[i].kind, NodeKind::Hidden | NodeKind::Output) {
+                steps.push((i, incoming[i].clone()));
+            }
+            for &to in outgoing[i].iter() {
+                num_pending[to] -= 1;
+                if num_pending[to] == 0 {
+                    ready.insert(to);
+                }
+            }
+        }
+
+        let of_kind = |kind| {
+            self.nodes
+                .iter()
+                .enumerate()
+                .filter(move |(_, n)| n.kind == kind)
+                .map(|(i, _)| i)
+        };
+        Graph {
+            num_nodes: self.nodes.len(),
+            bias: of_kind(NodeKind::Bias).next().unwrap_or(0),
+            steps,
+            outputs: of_kind(NodeKind::Output).collect(),
+            num_hidden: of_kind(NodeKind::Hidden).count(),
+        }
+    }
+
+    /// Joins two unconnected nodes, where it doesn't make a cycle
+    fn add_connection(
+        &mut self,
+        table: &mut InnovationTable,
+        config: &SimConfig,
+        rng: &mut impl Rng,
+    ) {
+        let targets: Vec<u32> = self
+            .nodes
+            .iter()
+            .filter(|n| matches!(n.kind, NodeKind::Hidden | NodeKind::Output))
+            .map(|n| n.id)
+            .collect();
+        let candidates: Vec<(u32, u32)> = self
+            .nodes
+            .iter()
+            .filter(|n| n.kind != NodeKind::Output)
+            .flat_map(|from| targets.iter().map(move |to| (from.id, *to)))
+            .filter(|(from, to)| {
+                !self
+                    .connections
+                    .iter()
+                    .any(|c| c.from == *from && c.to == *to)
+                    && !self.reaches(*to, *from)
+            })
+            .collect();
+        if candidates.is_empty() {
+            return;
+        }
+
+        let (from, to) = candidates[rng.gen_range(0..candidates.len())];
+        let spec = &config.brain_weight_gene;
+        self.insert_connection(ConnectionGene {
+            innovation: table.connection(from, to),
+            from,
+            to,
+            weight: rng.gen_range(spec.init.0..=spec.init.1),
+            enabled: true,
+        });
+    }
+
+    /// Splits an enabled connection with a new node, the connection into it has weight 1
+    /// and the one out of it the old weight, so the network behaves about the same
+    fn add_node(&mut self, table: &mut InnovationTable, config: &SimConfig, rng: &mut impl Rng) {
+        let enabled: Vec<usize> = (0..self.connections.len())
+            .filter(|i| self.connections[*i].enabled)
+            .collect();
+        if enabled.is_empty() {
+            return;
+        }
+
+        let i = enabled[rng.gen_range(0..enabled.len())];
+        let split = self.connections[i];
+        let id = table.split(split.innovation);
+        // Crossover can bring back a connection this lineage already split
+        if self.nodes.iter().any(|n| n.id == id) {
+            return;
+        }
+        self.connections[i].enabled = false;
+        let at = self.nodes.partition_point(|n| n.id < id);
+        self.nodes.insert(
+            at,
+            NodeGene {
+                id,
+                kind: NodeKind::Hidden,
+            },
+        );
+        let spec = &config.brain_weight_gene;
+        self.insert_connection(ConnectionGene {
+            innovation: table.connection(split.from, id),
+            from: split.from,
+            to: id,
+            weight: 1.0_f32.clamp(spec.min, spec.max),
+            enabled: true,
+        });
+        self.insert_connection(ConnectionGene {
+            innovation: table.connection(id, split.to),
+            from: id,
+            to: split.to,
+            weight: split.weight,
+            enabled: true,
+        });
+    }
+
+    /// Whether `to` can be reached from `from` over any connection, enabled or not
+    fn reaches(&self, from: u32, to: u32) -> bool {
+        let mut stack = vec![from];
+        let mut seen = BTreeSet::new();
+        while let Some(node) = stack.pop() {
+            if node == to {
+                return true;
+            }
+            if !seen.insert(node) {
+                continue;
+            }
+            stack.extend(
+                self.connections
+                    .iter()
+                    .filter(|c| c.from == node)
+                    .map(|c| c.to),
+            );
+        }
+        false
+    }
+
+    fn insert_connection(&mut self, connection: ConnectionGene) {
+        let at = self
+            .connections
+            .partition_point(|c| c.innovation < connection.innovation);
+        self.connections.insert(at, connection);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::SeedableRng;
+    use rand_chacha::ChaCha8Rng;
+
+    use super::*;
+    use crate::brain::Brain;
+
+    fn neat_config() -> SimConfig {
+        SimConfig {
+            controller: Controller::Neat {
+                add_connection: 1.0,
+                add_node: 1.0,
+            },
+            ..default()
+        }
+    }
+
+    fn node(id: u32, kind: NodeKind) -> NodeGene {
+        NodeGene { id, kind }
+    }
+
+    fn connection(innovation: u32, from: u32, to: u32, weight: f32) -> ConnectionGene {
+        ConnectionGene {
+            innovation,
+            from,
+            to,
+            weight,
+            enabled: true,
+        }
+    }
+
+    /// Each hidden and output node is evaluated once, after every node feeding it
+    fn assert_feed_forward(genome: &NeatGenome) {
+        let graph = genome.graph();
+        let num_computed = genome
+            .nodes
+            .iter()
+            .filter(|n| matches!(n.kind, NodeKind::Hidden | NodeKind::Output))
+            .count();
+        assert_eq!(graph.steps.len(), num_computed);
+        for (step, (_, incoming)) in graph.steps.iter().enumerate() {
+            for (from, _) in incoming {
+                let from_step = graph.steps.iter().position(|(node, _)| node == from);
+                assert!(from_step.is_none_or(|s| s < step));
+            }
+        }
+    }
+
+    #[test]
+    fn graph_evaluates_nodes_after_their_inputs() {
+        // Node 4 feeds node 3, so it must come first despite its higher id
+        let genome = NeatGenome {
+            nodes: vec![
+                node(0, NodeKind::Input),
+                node(1, NodeKind::Bias),
+                node(2, NodeKind::Output),
+                node(3, NodeKind::Hidden),
+                node(4, NodeKind::Hidden),
+            ],
+            connections: vec![
+                connection(0, 0, 4, 0.5),
+                connection(1, 4, 3, -1.0),
+                connection(2, 3, 2, 2.0),
+                connection(3, 1, 2, 0.25),
+            ],
+        };
+        let graph = genome.graph();
+        let order: Vec<usize> = graph.steps.iter().map(|(node, _)| *node).collect();
+        assert_eq!(order, vec![4, 3, 2]);
+        assert_eq!(graph.bias, 1);
+        assert_eq!(graph.num_hidden, 2);
+
+        let x = 0.8_f32;
+        let expected = ((-(0.5 * x).tanh()).tanh() * 2.0 + 0.25).tanh();
+        let steering = Brain::Graph(graph).steer(&[x]);
+        assert!((steering.x - expected).abs() < 1e-6);
+        assert_eq!(steering.y, 0.0);
+    }
+
+    #[test]
+    fn disabled_connections_are_not_evaluated() {
+        let mut genome = NeatGenome {
+            nodes: vec![
+                node(0, NodeKind::Input),
+                node(1, NodeKind::Bias),
+                node(2, NodeKind::Output),
+            ],
+            connections: vec![connection(0, 0, 2, 1.0)],
+        };
+        genome.connections[0].enabled = false;
+        let graph = genome.graph();
+        assert_eq!(graph.steps, vec![(2, vec![])]);
+        assert_eq!(Brain::Graph(graph).steer(&[1.0]).x, 0.0);
+    }
+
+    #[test]
+    fn reaches_follows_connections_in_their_direction() {
+        let genome = NeatGenome {
+            nodes: vec![
+                node(0, NodeKind::Input),
+                node(1, NodeKind::Bias),
+                node(2, NodeKind::Output),
+                node(3, NodeKind::Hidden),
+            ],
+            connections: vec![connection(0, 0, 3, 1.0), connection(1, 3, 2, 1.0)],
+        };
+        assert!(genome.reaches(0, 2));
+        assert!(genome.reaches(3, 3));
+        assert!(!genome.reaches(2, 0));
+        assert!(!genome.reaches(1, 2));
+    }
+
+    #[test]
+    fn structural_mutations_keep_the_network_acyclic() {
+        let config = neat_config();
+        let mut rng = ChaCha8Rng::seed_from_u64(1);
+        let mut innovations = Innovations::default();
+        let mut genome = NeatGenome::initial(&config, false, &mut rng);
+        for _ in 0..40 {
+            let table = innovations.table(false);
+            genome.add_node(table, &config, &mut rng);
+            genome.add_connection(table, &config, &mut rng);
+            assert_feed_forward(&genome);
+        }
+
+        let hidden: Vec<u32> = genome
+            .nodes
+            .iter()
+            .filter(|n| n.kind == NodeKind::Hidden)
+            .map(|n| n.id)
+            .collect();
+        assert!(!hidden.is_empty());
+        for c in genome.connections.iter() {
+            assert!(!genome.reaches(c.to, c.from));
+        }
+    }
+
+    #[test]
+    fn add_node_splits_a_connection_with_shared_innovations() {
+        let config = neat_config();
+        let mut rng = ChaCha8Rng::seed_from_u64(2);
+        let mut innovations = Innovations::default();
+        let parent = NeatGenome::initial(&config, true, &mut rng);
+        let num_connections = parent.connections.len();
+
+        // Same rng state, so both split the same connection
+        let mut a = parent.clone();
+        let mut b = parent.clone();
+        a.add_node(
+            innovations.table(true),
+            &config,
+            &mut ChaCha8Rng::seed_from_u64(3),
+        );
+        b.add_node(
+            innovations.table(true),
+            &config,
+            &mut ChaCha8Rng::seed_from_u64(3),
+        );
+        assert_eq!(a, b);
+        assert_eq!(a.connections.len(), num_connections + 2);
+        assert_eq!(a.connections.iter().filter(|c| !c.enabled).count(), 1);
+
+        let split = a.connections.iter().find(|c| !c.enabled).unwrap();
+        let new_node = a.nodes.iter().find(|n| n.kind == NodeKind::Hidden).unwrap();
+        let into = a.connections.iter().find(|c| c.to == new_node.id).unwrap();
+        let out = a
+            .connections
+            .iter()
+            .find(|c| c.from == new_node.id)
+            .unwrap();
+        assert_eq!((into.from, into.weight), (split.from, 1.0));
+        assert_eq!((out.to, out.weight), (split.to, split.weight));
+        assert!(a
+            .connections
+            .windows(2)
+            .all(|w| w[0].innovation < w[1].innovation));
+
+        // The predator table was used, the prey one is untouched
+        assert_eq!(
+            innovations.table(false).next_node,
+            InnovationTable::new(false).next_node
+        );
+    }
+
+    #[test]
+    fn crossover_keeps_the_parent_topology() {
+        let config = neat_config();
+        let mut rng = ChaCha8Rng::seed_from_u64(4);
+        let mut innovations = Innovations::default();
+        let parent = NeatGenome::initial(&config, false, &mut rng);
+        let mut mate = parent.clone();
+        mate.add_node(innovations.table(false), &config, &mut rng);
+        for c in mate.connections.iter_mut() {
+            c.weight = 3.0;
+        }
+
+        let child = parent.crossover(&mate, Crossover::Uniform, &mut rng);
+        assert_eq!(child.nodes, parent.nodes);
+        let innovations_of =
+            |g: &NeatGenome| -> Vec<u32> { g.connections.iter().map(|c| c.innovation).collect() };
+        assert_eq!(innovations_of(&child), innovations_of(&parent));
+        assert!(child.connections.iter().any(|c| c.weight == 3.0));
+
+        let blended = parent.crossover(&mate, Crossover::Blend, &mut rng);
+        for (c, p) in blended.connections.iter().zip(parent.connections.iter()) {
+            assert!(c.weight >= p.weight.min(3.0) && c.weight <= p.weight.max(3.0));
+        }
+    }
+
+    #[test]
+    fn other_controllers_get_an_empty_genome_without_drawing() {
+        let config = SimConfig::default();
+        let mut rng = ChaCha8Rng::seed_from_u64(5);
+        let before = rng.clone();
+        assert_eq!(
+            NeatGenome::initial(&config, false, &mut rng),
+            NeatGenome::default()
+        );
+        assert_eq!(rng, before);
+    }
+}
@@ -0,0 +1,222 @@
+use bevy::math::vec2;
+use bevy::prelude::*;
+use rand::Rng;
+
+use crate::utils::get_steering_force;
+use crate::*;
+
+pub struct ObstaclePlugin;
+
+#[derive(Component)]
+pub struct Obstacle(pub ObstacleShape);
+
+/// Pushed this far past the edge, so a boid on the edge doesn't count as inside
+const EDGE_MARGIN: f32 = 0.01;
+
+impl Plugin for ObstaclePlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(
+            FixedUpdate,
+            sync_obstacles
+                .in_set(SimSet::World)
+                .run_if(in_state(SimState::Simulating)),
+        );
+    }
+}
+
+/// Keeps the obstacle entities in line with `SimConfig::obstacles`, respawning them on change
+pub fn sync_obstacles(
+    mut commands: Commands,
+    config: Res<SimConfig>,
+    obstacles_query: Query<(Entity, &Obstacle)>,
+) {
+    let is_synced = obstacles_query.iter().len() == config.obstacles.len()
+        && obstacles_query
+            .iter()
+            .zip(config.obstacles.iter())
+            .all(|((_, o), shape)| o.0 == *shape);
+    if is_synced {
+        return;
+    }
+
+    for (e, _) in obstacles_query.iter() {
+        commands.entity(e).despawn();
+    }
+    for shape in config.obstacles.iter() {
+        commands.spawn(Obstacle(*shape));
+    }
+}
+
+/// Whether `pos` is inside any obstacle
+pub fn is_blocked<'a>(pos: Vec2, obstacles: impl IntoIterator<Item = &'a Obstacle>) -> bool {
+    obstacles.into_iter().any(|o| o.0.contains(pos))
+}
+
+/// Random position in the world outside every obstacle,
+/// `None` if `MAX_SPAWN_TRIES` draws all landed inside one
+pub fn get_free_pos(
+    config: &SimConfig,
+    rng: &mut impl Rng,
+    obstacles_query: &Query<&Obstacle>,
+) -> Option<Vec2> {
+    (0..MAX_SPAWN_TRIES)
+        .map(|_| {
+            let x = rng.gen_range(-config.world_w..config.world_w);
+            let y = rng.gen_range(-config.world_h..config.world_h);
+            vec2(x, y)
+        })
+        .find(|pos| !is_blocked(*pos, obstacles_query))
+}
+
+/// Whether nothing blocks the straight line between two points
+pub fn is_visible<'a>(
+    from: Vec2,
+    to: Vec2,
+    obstacles: impl IntoIterator<Item = &'a Obstacle>,
+) -> bool {
+    !obstacles.into_iter().any(|o| o.0.blocks_line(from, to))
+}
+
+/// Moves a position that ended up inside an obstacle back out over the closest edge,
+/// turning the velocity so it no longer points into the obstacle
+pub fn push_out_of_obstacles<'a>(
+    pos: &mut Vec2,
+    velocity: &mut Vec2,
+    obstacles: impl IntoIterator<Item = &'a Obstacle>,
+) {
+    for o in obstacles {
+        if !o.0.contains(*pos) {
+            continue;
+        }
+        let (edge, normal) = o.0.closest_edge(*pos);
+        *pos = edge + normal * EDGE_MARGIN;
+
+        let into_obstacle = velocity.dot(normal);
+        if into_obstacle < 0.0 {
+            *velocity -= 2.0 * into_obstacle * normal;
+        }
+    }
+}
+
+/// Steering away from the obstacles within `range`, stronger the closer they are
+pub fn get_avoidance_force<'a>(
+    pos: Vec2,
+    velocity: Vec2,
+    range: f32,
+    obstacles: impl IntoIterator<Item = &'a Obstacle>,
+) -> Vec2 {
+    let mut force = Vec2::ZERO;
+    for o in obstacles {
+        let (edge, _) = o.0.closest_edge(pos);
+        let dist = edge.distance(pos);
+        if dist < range {
+            force += get_steering_force(edge, pos, velocity) * -(1.0 - dist / range);
+        }
+    }
+
+    force
+}
+
+impl ObstacleShape {
+    pub fn contains(&self, pos: Vec2) -> bool {
+        match *self {
+            ObstacleShape::Circle { center, radius } => {
+                pos.distance_squared(center.into()) < radius * radius
+            }
+            ObstacleShape::Rect { size, .. } => {
+                let local = self.local_pos(pos).abs();
+                local.x < size.0 / 2.0 && local.y < size.1 / 2.0
+            }
+        }
+    }
+
+    /// Point on the edge closest to `pos`, along with the outward normal there
+    pub fn closest_edge(&self, pos: Vec2) -> (Vec2, Vec2) {
+        match *self {
+            ObstacleShape::Circle { center, radius } => {
+                let center = Vec2::from(center);
+                let normal = (pos - center).try_normalize().unwrap_or(Vec2::X);
+                (center + normal * radius, normal)
+            }
+            ObstacleShape::Rect { center, size, .. } => {
+                let half = Vec2::from(size) / 2.0;
+                let local = self.local_pos(pos);
+                let (edge, normal) = if self.contains(pos) {
+                    // Leave through the nearest side
+                    let depth = half - local.abs();
+                    if depth.x < depth.y {
+                        let side = local.x.signum();
+                        (vec2(side * half.x, local.y), vec2(side, 0.0))
+                    } else {
+                        let side = local.y.signum();
+                        (vec2(local.x, side * half.y), vec2(0.0, side))
+                    }
+                } else {
+                    let edge = local.clamp(-half, half);
+                    (edge, (local - edge).try_normalize().unwrap_or(Vec2::X))
+                };
+
+                let rotation = self.rotation();
+                (
+                    Vec2::from(center) + rotation.rotate(edge),
+                    rotation.rotate(normal),
+                )
+            }
+        }
+    }
+
+    /// Whether the segment between two points passes through the obstacle
+    pub fn blocks_line(&self, from: Vec2, to: Vec2) -> bool {
+        match *self {
+            ObstacleShape::Circle { center, radius } => {
+                let center = Vec2::from(center);
+                let line = to - from;
+                let t = if line == Vec2::ZERO {
+                    0.0
+                } else {
+                    ((center - from).dot(line) / line.length_squared()).clamp(0.0, 1.0)
+                };
+                (from + line * t).distance_squared(center) < radius * radius
+            }
+            ObstacleShape::Rect { size, .. } => {
+                // Slab test in the rectangle's own frame
+                let half = Vec2::from(size) / 2.0;
+                let from = self.local_pos(from);
+                let line = self.local_pos(to) - from;
+                let (mut t_min, mut t_max) = (0.0_f32, 1.0_f32);
+                for axis in 0..2 {
+                    if line[axis].abs() < f32::EPSILON {
+                        if from[axis].abs() >= half[axis] {
+                            return false;
+                        }
+                        continue;
+                    }
+                    let t0 = (-half[axis] - from[axis]) / line[axis];
+                    let t1 = (half[axis] - from[axis]) / line[axis];
+                    t_min = t_min.max(t0.min(t1));
+                    t_max = t_max.min(t0.max(t1));
+                    if t_min >= t_max {
+                        return false;
+                    }
+                }
+                true
+            }
+        }
+    }
+
+    fn rotation(&self) -> Vec2 {
+        match *self {
+            ObstacleShape::Circle { .. } => Vec2::X,
+            ObstacleShape::Rect { angle, .. } => Vec2::from_angle(angle.to_radians()),
+        }
+    }
+
+    fn local_pos(&self, pos: Vec2) -> Vec2 {
+        let center = match *self {
+            ObstacleShape::Circle { center, .. } | ObstacleShape::Rect { center, .. } => center,
+        };
+        let rotation = self.rotation();
+        // Rotating by the conjugate undoes the rotation
+        vec2(rotation.x, -rotation.y).rotate(pos - Vec2::from(center))
+    }
+}
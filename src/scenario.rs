@@ -0,0 +1,490 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+use bevy::ecs::system::SystemParam;
+use bevy::math::vec2;
+use bevy::prelude::*;
+use rand::seq::index::sample;
+use rand::Rng;
+use serde::Deserialize;
+
+use crate::biome::{Biome, BiomeMap};
+use crate::boid::{Boid, BoidBundle, Newborn, Predator, PredatorBundle};
+use crate::brain::gene_specs;
+use crate::clock::SimClock;
+use crate::elements::{Consumable, Food, FoodBundle, PoisonBundle};
+use crate::genome::Genome;
+use crate::lineage::Ancestry;
+use crate::neat::NeatGenome;
+use crate::obstacles::{is_blocked, Obstacle};
+use crate::snapshot::PendingSnapshot;
+use crate::stats::SimulationStats;
+use crate::utils::{get_rand_unit_vec2, limit_to_world, world_distance_squared};
+use crate::*;
+
+pub struct ScenarioPlugin;
+
+/// Scripted events for reproducible experiments, loaded from a ron file with `--scenario`.
+/// Every event fires once, as soon as its trigger is met.
+#[derive(Resource, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Scenario {
+    pub events: Vec<ScenarioEvent>,
+    /// Tick each event fired at, saved in snapshots
+    #[serde(skip)]
+    pub fired: Vec<Option<u64>>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ScenarioEvent {
+    pub trigger: Trigger,
+    pub action: Action,
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+pub enum Trigger {
+    /// Sim-seconds since the start of the run
+    At(f32),
+    /// Fewer than `count` of `kind` are alive
+    Below { kind: EntityKind, count: usize },
+    /// More than `count` of `kind` are alive
+    Above { kind: EntityKind, count: usize },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+pub enum EntityKind {
+    Boid,
+    Predator,
+    Food,
+    Poison,
+}
+
+/// Circle in world units
+#[derive(Debug, Clone, Copy, Deserialize)]
+pub struct Region {
+    pub center: (f32, f32),
+    pub radius: f32,
+}
+
+#[derive(Clone, Deserialize)]
+pub enum Action {
+    /// Spawns `count` of `kind` in the region, or anywhere in the world.
+    /// Agents get random genes, except for the ones given by name.
+    Spawn {
+        kind: EntityKind,
+        count: usize,
+        #[serde(default)]
+        region: Option<Region>,
+        #[serde(default)]
+        genes: BTreeMap<String, f32>,
+    },
+    /// Despawns a random `fraction` of `kind` in the region, or in the whole world
+    Cull {
+        kind: EntityKind,
+        fraction: f32,
+        #[serde(default)]
+        region: Option<Region>,
+    },
+    /// Sets a config field to a value in ron syntax
+    SetConfig { field: String, value: String },
+    /// Multiplies a numeric config field
+    ScaleConfig { field: String, factor: f32 },
+    /// Turns the biome cells in the region into `biome`, e.g. to make an area fertile
+    SetRegionBiome { region: Region, biome: Biome },
+}
+
+#[derive(Debug)]
+pub enum ScenarioError {
+    Io(std::io::Error),
+    Parse(ron::error::SpannedError),
+    Invalid(Vec<String>),
+}
+
+impl Plugin for ScenarioPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(
+            FixedUpdate,
+            run_scenario
+                .in_set(SimSet::Scenario)
+                .run_if(resource_exists::<Scenario>())
+                .run_if(in_state(SimState::Simulating)),
+        );
+    }
+}
+
+/// What scenario events count, spawn into, cull from or change
+#[derive(SystemParam)]
+struct ScenarioWorld<'w, 's> {
+    config: ResMut<'w, SimConfig>,
+    biomes: ResMut<'w, BiomeMap>,
+    ancestry: ResMut<'w, Ancestry>,
+    agents_query: Query<'w, 's, (Entity, &'static Transform, Has<Predator>), With<Boid>>,
+    consumables_query: Query<'w, 's, (Entity, &'static Transform, Has<Food>), With<Consumable>>,
+    obstacles_query: Query<'w, 's, &'static Obstacle>,
+}
+
+fn run_scenario(
+    mut commands: Commands,
+    clock: Res<SimClock>,
+    mut rng: ResMut<SimRng>,
+    mut stats: ResMut<SimulationStats>,
+    mut scenario: ResMut<Scenario>,
+    handle: Res<GlobalTextureHandle>,
+    world: ScenarioWorld,
+) {
+    let ScenarioWorld {
+        mut config,
+        mut biomes,
+        mut ancestry,
+        agents_query,
+        consumables_query,
+        obstacles_query,
+    } = world;
+    let entities = |kind: EntityKind| -> Vec<(Entity, Vec2)> {
+        let agents = agents_query
+            .iter()
+            .filter(|(_, _, is_predator)| *is_predator == (kind == EntityKind::Predator));
+        let consumables = consumables_query
+            .iter()
+            .filter(|(_, _, is_food)| *is_food == (kind == EntityKind::Food));
+        match kind {
+            EntityKind::Boid | EntityKind::Predator => agents
+                .map(|(e, t, _)| (e, t.translation.truncate()))
+                .collect(),
+            EntityKind::Food | EntityKind::Poison => consumables
+                .map(|(e, t, _)| (e, t.translation.truncate()))
+                .collect(),
+        }
+    };
+
+    let scenario = &mut *scenario;
+    for (event, fired) in scenario.events.iter().zip(scenario.fired.iter_mut()) {
+        if fired.is_some() {
+            continue;
+        }
+        let is_triggered = match event.trigger {
+            Trigger::At(secs) => clock.tick >= (secs * SIM_TICK_RATE).round() as u64,
+            Trigger::Below { kind, count } => entities(kind).len() < count,
+            Trigger::Above { kind, count } => entities(kind).len() > count,
+        };
+        if !is_triggered {
+            continue;
+        }
+        *fired = Some(clock.tick);
+
+        let rng = &mut rng.0;
+        let label = match &event.action {
+            Action::Spawn {
+                kind,
+                count,
+                region,
+                genes,
+            } => {
+                let mut num_spawned = 0;
+                for _ in 0..*count {
+                    let Some(pos) = get_spawn_pos(*region, &config, rng, &obstacles_query) else {
+                        continue;
+                    };
+                    let pos = (pos.x, pos.y);
+                    let atlas = handle.atlas();
+                    let mut genome = |specs: &[GeneSpec]| {
+                        let mut genome = Genome::rand(specs, rng);
+                        // The config may have been reloaded with new bounds since validation
+                        for (name, value) in genes.iter() {
+                            let value = specs
+                                .iter()
+                                .find(|s| s.name == name.as_str())
+                                .map_or(*value, |s| value.clamp(s.min, s.max));
+                            genome.0.insert(name.clone(), value);
+                        }
+                        genome
+                    };
+                    match kind {
+                        EntityKind::Boid => {
+                            let genome = genome(&gene_specs(&config, false));
+                            let neat = NeatGenome::initial(&config, false, rng);
+                            let lineage = ancestry.founder(&genome, false, clock.tick);
+                            let newborn = Newborn {
+                                pos,
+                                genome,
+                                neat,
+                                lineage,
+                            };
+                            commands.spawn(BoidBundle::child(
+                                newborn, atlas, false, clock.tick, &config, rng,
+                            ));
+                        }
+                        EntityKind::Predator => {
+                            let genome = genome(&gene_specs(&config, true));
+                            let neat = NeatGenome::initial(&config, true, rng);
+                            let lineage = ancestry.founder(&genome, true, clock.tick);
+                            let newborn = Newborn {
+                                pos,
+                                genome,
+                                neat,
+                                lineage,
+                            };
+                            commands.spawn(PredatorBundle::child(
+                                newborn, atlas, clock.tick, &config, rng,
+                            ));
+                        }
+                        EntityKind::Food => {
+                            commands
+                                .spawn(FoodBundle::new(pos, atlas, clock.tick, &config, &biomes));
+                        }
+                        EntityKind::Poison => {
+                            commands.spawn(PoisonBundle::new(pos, atlas, clock.tick, &config));
+                        }
+                    }
+                    num_spawned += 1;
+                }
+                format!("spawned {} {:?}", num_spawned, kind)
+            }
+            Action::Cull {
+                kind,
+                fraction,
+                region,
+            } => {
+                let candidates: Vec<Entity> = entities(*kind)
+                    .into_iter()
+                    .filter(|(_, pos)| region.is_none_or(|r| r.contains(*pos, &config)))
+                    .map(|(e, _)| e)
+                    .collect();
+                let num_culled = (candidates.len() as f32 * fraction).round() as usize;
+                for i in sample(rng, candidates.len(), num_culled) {
+                    commands.entity(candidates[i]).despawn();
+                }
+                format!("culled {} {:?}", num_culled, kind)
+            }
+            action => match action.apply_persistent(&mut config, &mut biomes) {
+                Ok(label) => label,
+                Err(e) => {
+                    error!("Scenario action failed: {}", e);
+                    continue;
+                }
+            },
+        };
+
+        info!("Scenario at {:.1}s: {}", clock.elapsed_secs(), label);
+        stats.add_marker(clock.elapsed_secs(), format!("Scenario: {}", label));
+    }
+}
+
+/// Re-applies the config and biome changes of events that fired before the pending
+/// snapshot was taken, entity changes are part of the snapshot already
+pub fn resume_scenario(
+    pending: Res<PendingSnapshot>,
+    scenario: Option<ResMut<Scenario>>,
+    mut config: ResMut<SimConfig>,
+    mut biomes: ResMut<BiomeMap>,
+) {
+    let Some(mut scenario) = scenario else {
+        return;
+    };
+    let fired = &pending.0.scenario_fired;
+    if fired.len() != scenario.events.len() {
+        if !fired.is_empty() {
+            warn!("Snapshot was taken with a different scenario, starting it from scratch");
+        }
+        return;
+    }
+
+    let mut order: Vec<(u64, usize)> = fired
+        .iter()
+        .enumerate()
+        .filter_map(|(i, tick)| tick.map(|tick| (tick, i)))
+        .collect();
+    order.sort_unstable();
+    for (_, i) in order {
+        if let Err(e) = scenario.events[i]
+            .action
+            .apply_persistent(&mut config, &mut biomes)
+        {
+            error!("Failed to replay scenario action: {}", e);
+        }
+    }
+    scenario.fired = fired.clone();
+}
+
+/// Random free position in the region or the world, None if nothing free turned up
+fn get_spawn_pos(
+    region: Option<Region>,
+    config: &SimConfig,
+    rng: &mut impl Rng,
+    obstacles_query: &Query<&Obstacle>,
+) -> Option<Vec2> {
+    for _ in 0..MAX_SPAWN_TRIES {
+        let pos = match region {
+            Some(r) => {
+                // sqrt keeps the points uniform over the area
+                let dist = r.radius * rng.gen_range(0.0..1.0_f32).sqrt();
+                Vec2::from(r.center) + get_rand_unit_vec2(rng) * dist
+            }
+            None => vec2(
+                rng.gen_range(-config.world_w..config.world_w),
+                rng.gen_range(-config.world_h..config.world_h),
+            ),
+        };
+        let pos = Vec2::from(limit_to_world(pos.into(), config));
+        if !is_blocked(pos, obstacles_query) {
+            return Some(pos);
+        }
+    }
+
+    None
+}
+
+impl Scenario {
+    /// Reads the scenario and checks it against the config it will run with
+    pub fn load(path: &Path, config: &SimConfig) -> Result<Self, ScenarioError> {
+        let contents = fs::read_to_string(path).map_err(ScenarioError::Io)?;
+        let mut scenario: Self = ron::from_str(&contents).map_err(ScenarioError::Parse)?;
+        scenario.fired = vec![None; scenario.events.len()];
+
+        let errors: Vec<String> = scenario
+            .events
+            .iter()
+            .enumerate()
+            .filter_map(|(i, e)| {
+                e.validate(config)
+                    .err()
+                    .map(|e| format!("event {}: {}", i, e))
+            })
+            .collect();
+        if !errors.is_empty() {
+            return Err(ScenarioError::Invalid(errors));
+        }
+
+        Ok(scenario)
+    }
+}
+
+impl ScenarioEvent {
+    fn validate(&self, config: &SimConfig) -> Result<(), String> {
+        if let Trigger::At(secs) = self.trigger {
+            if secs < 0.0 {
+                return Err("trigger time must not be negative".to_string());
+            }
+        }
+
+        match &self.action {
+            Action::Spawn {
+                kind,
+                region,
+                genes,
+                ..
+            } => {
+                let specs: &[GeneSpec] = match kind {
+                    EntityKind::Boid => &config.boid_genes,
+                    EntityKind::Predator => &config.predator_genes,
+                    EntityKind::Food | EntityKind::Poison => &[],
+                };
+                for (name, value) in genes.iter() {
+                    let Some(spec) = specs.iter().find(|s| s.name == name.as_str()) else {
+                        return Err(format!("{:?} has no gene `{}`", kind, name));
+                    };
+                    if !(spec.min..=spec.max).contains(value) {
+                        return Err(format!(
+                            "gene `{}` must be between {} and {}, got {}",
+                            name, spec.min, spec.max, value
+                        ));
+                    }
+                }
+                Region::validate(region.as_ref())
+            }
+            Action::Cull {
+                fraction, region, ..
+            } => {
+                if !(0.0..=1.0).contains(fraction) {
+                    return Err("cull fraction must be between 0 and 1".to_string());
+                }
+                Region::validate(region.as_ref())
+            }
+            Action::SetRegionBiome { region, .. } => Region::validate(Some(region)),
+            action => {
+                let mut config = config.clone();
+                let mut biomes = BiomeMap::default();
+                action
+                    .apply_persistent(&mut config, &mut biomes)
+                    .map(|_| ())
+            }
+        }
+    }
+}
+
+impl Action {
+    /// Applies a config or biome change, returning a description of it.
+    /// Spawning and culling act on entities and aren't handled here.
+    fn apply_persistent(
+        &self,
+        config: &mut SimConfig,
+        biomes: &mut BiomeMap,
+    ) -> Result<String, String> {
+        let new_config = match self {
+            Action::SetConfig { field, value } => config.with_field(field, value),
+            Action::ScaleConfig { field, factor } => {
+                let value = config
+                    .field(field)
+                    .ok_or_else(|| format!("unknown config field `{}`", field))?;
+                // Integer fields stay integers
+                let scaled = if let Ok(v) = value.parse::<i64>() {
+                    ((v as f64 * *factor as f64).round() as i64).to_string()
+                } else if let Ok(v) = value.parse::<f32>() {
+                    format!("{:?}", v * factor)
+                } else {
+                    return Err(format!("config field `{}` is not a number", field));
+                };
+                config.with_field(field, &scaled)
+            }
+            Action::SetRegionBiome { region, biome } => {
+                biomes.paint(region.center.into(), region.radius, *biome);
+                return Ok(format!(
+                    "{:?} at ({}, {}) radius {}",
+                    biome, region.center.0, region.center.1, region.radius
+                ));
+            }
+            Action::Spawn { .. } | Action::Cull { .. } => {
+                return Err("only config and biome changes can be applied here".to_string());
+            }
+        }
+        .map_err(|e| e.to_string())?;
+
+        let label = config.diff(&new_config).join(", ");
+        *config = new_config;
+        Ok(label)
+    }
+}
+
+impl Region {
+    fn contains(&self, pos: Vec2, config: &SimConfig) -> bool {
+        world_distance_squared(self.center.into(), pos, config) <= self.radius * self.radius
+    }
+
+    fn validate(region: Option<&Region>) -> Result<(), String> {
+        match region {
+            Some(r) if r.radius <= 0.0 => Err("region radius must be greater than 0".to_string()),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl fmt::Display for ScenarioError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ScenarioError::Io(e) => write!(f, "failed to read scenario: {}", e),
+            ScenarioError::Parse(e) => write!(f, "failed to parse scenario: {}", e),
+            ScenarioError::Invalid(errors) => {
+                write!(f, "invalid scenario:")?;
+                for e in errors {
+                    write!(f, "\n  - {}", e)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl std::error::Error for ScenarioError {}
@@ -0,0 +1,121 @@
+use std::fmt;
+
+use bevy::prelude::*;
+
+use crate::clock::SimClock;
+use crate::*;
+
+pub struct SeasonPlugin;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Season {
+    Spring,
+    Summer,
+    Autumn,
+    Winter,
+}
+
+/// Where the simulation is in the season and day/night cycles.
+/// Derived from the sim clock alone, so a restored snapshot picks up in the same phase.
+#[derive(Resource, Debug, Default, Clone, Copy, PartialEq)]
+pub struct SeasonCycle {
+    /// None when seasons are disabled
+    pub season: Option<Season>,
+    pub is_night: bool,
+    /// Days since the start of the run, 0 when nights are disabled
+    pub day: u64,
+}
+
+/// Modifiers used while seasons are disabled
+const NO_SEASON: SeasonParams = SeasonParams {
+    food_growth: 1.0,
+    food_decay: 1.0,
+    poison_growth: 1.0,
+};
+
+impl Plugin for SeasonPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<SeasonCycle>().add_systems(
+            FixedUpdate,
+            update_season_cycle
+                .in_set(SimSet::World)
+                .run_if(in_state(SimState::Simulating)),
+        );
+    }
+}
+
+fn update_season_cycle(
+    clock: Res<SimClock>,
+    config: Res<SimConfig>,
+    mut cycle: ResMut<SeasonCycle>,
+) {
+    cycle.set_if_neq(SeasonCycle::at(clock.tick, &config));
+}
+
+impl Season {
+    const ALL: [Season; 4] = [
+        Season::Spring,
+        Season::Summer,
+        Season::Autumn,
+        Season::Winter,
+    ];
+
+    pub fn params(self, config: &SimConfig) -> &SeasonParams {
+        match self {
+            Season::Spring => &config.season_spring,
+            Season::Summer => &config.season_summer,
+            Season::Autumn => &config.season_autumn,
+            Season::Winter => &config.season_winter,
+        }
+    }
+
+    pub fn index(self) -> usize {
+        self as usize
+    }
+}
+
+impl SeasonCycle {
+    pub fn at(tick: u64, config: &SimConfig) -> Self {
+        let to_ticks = |secs: f32| (secs * SIM_TICK_RATE).round().max(1.0) as u64;
+
+        let season = (config.season_length > 0.0).then(|| {
+            let season_ticks = to_ticks(config.season_length);
+            Season::ALL[(tick / season_ticks % Season::ALL.len() as u64) as usize]
+        });
+        let (day, is_night) = if config.day_length > 0.0 {
+            // Each day starts with daylight, the second half is night
+            let day_ticks = to_ticks(config.day_length);
+            (tick / day_ticks, tick % day_ticks >= day_ticks / 2)
+        } else {
+            (0, false)
+        };
+
+        Self {
+            season,
+            is_night,
+            day,
+        }
+    }
+
+    pub fn params<'a>(&self, config: &'a SimConfig) -> &'a SeasonParams {
+        match self.season {
+            Some(season) => season.params(config),
+            None => &NO_SEASON,
+        }
+    }
+
+    /// Scale of every perception radius, smaller at night
+    pub fn perception(&self, config: &SimConfig) -> f32 {
+        if self.is_night {
+            config.night_perception
+        } else {
+            1.0
+        }
+    }
+}
+
+impl fmt::Display for Season {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(self, f)
+    }
+}
@@ -0,0 +1,382 @@
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use bevy::ecs::system::SystemParam;
+use bevy::prelude::*;
+use rand_chacha::ChaCha8Rng;
+use serde::{Deserialize, Serialize};
+
+use crate::boid::{
+    BirthTimeStamp, BoidBundle, Health, Predator, PredatorBundle, PreyFilter, ReplicateTimer,
+    Velocity,
+};
+use crate::clock::SimClock;
+use crate::elements::{Consumable, FoodBundle, LastReplicationTs, PoisonBundle};
+use crate::genome::Genome;
+use crate::lineage::{Ancestry, Lineage};
+use crate::neat::{Innovations, NeatGenome};
+use crate::scenario::Scenario;
+use crate::species::{Species, SpeciesRegistry};
+use crate::stats::SimulationStats;
+use crate::*;
+
+pub struct SnapshotPlugin;
+
+/// Everything needed to resume a simulation, written to disk as ron
+#[derive(Serialize, Deserialize)]
+pub struct WorldSnapshot {
+    pub version: u32,
+    pub tick: u64,
+    pub rng: ChaCha8Rng,
+    pub boids: Vec<BoidSnapshot>,
+    pub predators: Vec<PredatorSnapshot>,
+    pub consumables: Vec<ConsumableSnapshot>,
+    pub stats: SimulationStats,
+    pub ancestry: Ancestry,
+    pub species: SpeciesRegistry,
+    pub innovations: Innovations,
+    /// Tick each scenario event fired at, empty without a scenario
+    #[serde(default)]
+    pub scenario_fired: Vec<Option<u64>>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct BoidSnapshot {
+    pub pos: (f32, f32),
+    pub velocity: (f32, f32),
+    pub health: f32,
+    pub genome: Genome,
+    pub neat: NeatGenome,
+    pub lineage: Lineage,
+    pub species: Option<u64>,
+    pub replicate_elapsed: Duration,
+    pub birth_tick: u64,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct PredatorSnapshot {
+    pub boid: BoidSnapshot,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ConsumableSnapshot {
+    pub kind: ConsumableKind,
+    pub pos: (f32, f32),
+    pub nutrition: f32,
+    pub last_replication_tick: u64,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub enum ConsumableKind {
+    Food,
+    Poison,
+}
+
+/// Snapshot to start from instead of populating a fresh world
+#[derive(Resource)]
+pub struct PendingSnapshot(pub WorldSnapshot);
+
+/// Writes the current world to the given path, or to `SNAPSHOT_DIR` when none is given
+#[derive(Event)]
+pub struct SaveSnapshot(pub Option<PathBuf>);
+
+#[derive(Debug)]
+pub enum SnapshotError {
+    Io(std::io::Error),
+    Parse(ron::error::SpannedError),
+    Serialize(ron::Error),
+    Version(u32),
+}
+
+type BoidSnapshotQuery = (
+    &'static Transform,
+    &'static Velocity,
+    &'static Health,
+    &'static Genome,
+    &'static NeatGenome,
+    &'static Lineage,
+    &'static Species,
+    &'static ReplicateTimer,
+    &'static BirthTimeStamp,
+);
+
+/// Read access to everything that goes into a `WorldSnapshot`
+#[derive(SystemParam)]
+pub struct SnapshotSource<'w, 's> {
+    clock: Res<'w, SimClock>,
+    rng: Res<'w, SimRng>,
+    stats: Res<'w, SimulationStats>,
+    ancestry: Res<'w, Ancestry>,
+    species: Res<'w, SpeciesRegistry>,
+    innovations: Res<'w, Innovations>,
+    scenario: Option<Res<'w, Scenario>>,
+    boids_query: Query<'w, 's, BoidSnapshotQuery, PreyFilter>,
+    predators_query: Query<'w, 's, BoidSnapshotQuery, With<Predator>>,
+    consumables_query: Query<
+        'w,
+        's,
+        (
+            &'static Transform,
+            &'static Consumable,
+            &'static LastReplicationTs,
+        ),
+    >,
+}
+
+/// Resources a restored `WorldSnapshot` overwrites, its entities are spawned separately
+#[derive(SystemParam)]
+pub struct SnapshotTarget<'w> {
+    clock: ResMut<'w, SimClock>,
+    rng: ResMut<'w, SimRng>,
+    stats: ResMut<'w, SimulationStats>,
+    ancestry: ResMut<'w, Ancestry>,
+    species: ResMut<'w, SpeciesRegistry>,
+    innovations: ResMut<'w, Innovations>,
+}
+
+impl Plugin for SnapshotPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<SaveSnapshot>().add_systems(
+            Last,
+            (save_snapshot, autosave).run_if(in_state(SimState::Simulating)),
+        );
+    }
+}
+
+pub fn save_snapshot(source: SnapshotSource, mut events: EventReader<SaveSnapshot>) {
+    for e in events.read() {
+        let tick = source.clock.tick;
+        let path =
+            e.0.clone()
+                .unwrap_or_else(|| Path::new(SNAPSHOT_DIR).join(format!("snapshot_{}.ron", tick)));
+
+        match source.capture().save(&path) {
+            Ok(()) => info!("Saved snapshot at tick {} to {}", tick, path.display()),
+            Err(e) => error!("Failed to save snapshot to {}: {}", path.display(), e),
+        }
+    }
+}
+
+/// Writes a checkpoint to `CHECKPOINT_DIR` every `autosave_interval` sim-minutes,
+/// keeping only the newest `autosave_keep`
+fn autosave(source: SnapshotSource, config: Res<SimConfig>, mut last_tick: Local<Option<u64>>) {
+    let tick = source.clock.tick;
+    // The first check only records the tick, so a resumed run doesn't rewrite its own checkpoint
+    let Some(prev_tick) = last_tick.replace(tick) else {
+        return;
+    };
+    if config.autosave_interval <= 0.0 {
+        return;
+    }
+    let interval_ticks = (config.autosave_interval * 60.0 * SIM_TICK_RATE)
+        .round()
+        .max(1.0) as u64;
+    if tick / interval_ticks == prev_tick / interval_ticks {
+        return;
+    }
+
+    let dir = Path::new(CHECKPOINT_DIR);
+    let path = dir.join(format!("checkpoint_{:012}.ron", tick));
+    match source.capture().save(&path) {
+        Ok(()) => info!("Saved checkpoint at tick {} to {}", tick, path.display()),
+        Err(e) => {
+            error!("Failed to save checkpoint to {}: {}", path.display(), e);
+            return;
+        }
+    }
+
+    let checkpoints = match list_checkpoints(dir) {
+        Ok(checkpoints) => checkpoints,
+        Err(e) => {
+            error!("Failed to list checkpoints in {}: {}", dir.display(), e);
+            return;
+        }
+    };
+    let num_old = checkpoints.len().saturating_sub(config.autosave_keep);
+    for old in checkpoints.iter().take(num_old) {
+        if let Err(e) = fs::remove_file(old) {
+            warn!("Failed to remove old checkpoint {}: {}", old.display(), e);
+        }
+    }
+}
+
+/// Checkpoints in `dir`, oldest first
+pub fn list_checkpoints(dir: &Path) -> io::Result<Vec<PathBuf>> {
+    if !dir.exists() {
+        return Ok(Vec::new());
+    }
+
+    let mut checkpoints = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        let is_checkpoint = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .is_some_and(|name| name.starts_with("checkpoint_") && name.ends_with(".ron"));
+        if is_checkpoint {
+            checkpoints.push(path);
+        }
+    }
+    // Ticks are zero padded, so name order is tick order
+    checkpoints.sort();
+
+    Ok(checkpoints)
+}
+
+/// Spawns the pending snapshot in place of a freshly populated world
+pub fn restore_snapshot(
+    mut commands: Commands,
+    mut target: SnapshotTarget,
+    pending: Res<PendingSnapshot>,
+    config: Res<SimConfig>,
+    handle: Res<GlobalTextureHandle>,
+) {
+    let snapshot = &pending.0;
+    target.restore(snapshot);
+
+    // Same spawn order as a fresh world, boids first then predators, food then poison
+    for b in snapshot.boids.iter() {
+        commands.spawn(BoidBundle::restore(b, handle.atlas(), false, &config));
+    }
+    for p in snapshot.predators.iter() {
+        commands.spawn(PredatorBundle::restore(p, handle.atlas(), &config));
+    }
+    for kind in [ConsumableKind::Food, ConsumableKind::Poison] {
+        for c in snapshot.consumables.iter().filter(|c| c.kind == kind) {
+            match kind {
+                ConsumableKind::Food => {
+                    commands.spawn(FoodBundle::restore(c, handle.atlas(), &config))
+                }
+                ConsumableKind::Poison => {
+                    commands.spawn(PoisonBundle::restore(c, handle.atlas(), &config))
+                }
+            };
+        }
+    }
+
+    info!(
+        "Restored snapshot at tick {} with {} boids, {} predators and {} consumables",
+        snapshot.tick,
+        snapshot.boids.len(),
+        snapshot.predators.len(),
+        snapshot.consumables.len()
+    );
+}
+
+impl SnapshotTarget<'_> {
+    pub fn restore(&mut self, snapshot: &WorldSnapshot) {
+        self.clock.tick = snapshot.tick;
+        self.rng.0 = snapshot.rng.clone();
+        *self.stats = snapshot.stats.clone();
+        *self.ancestry = snapshot.ancestry.clone();
+        *self.species = snapshot.species.clone();
+        *self.innovations = snapshot.innovations.clone();
+    }
+}
+
+impl SnapshotSource<'_, '_> {
+    pub fn capture(&self) -> WorldSnapshot {
+        let boid_snapshot = |(t, v, h, g, n, l, s, r, b): (
+            &Transform,
+            &Velocity,
+            &Health,
+            &Genome,
+            &NeatGenome,
+            &Lineage,
+            &Species,
+            &ReplicateTimer,
+            &BirthTimeStamp,
+        )| BoidSnapshot {
+            pos: (t.translation.x, t.translation.y),
+            velocity: (v.0.x, v.0.y),
+            health: h.0,
+            genome: g.clone(),
+            neat: n.clone(),
+            lineage: *l,
+            species: s.0,
+            replicate_elapsed: r.0.elapsed(),
+            birth_tick: b.0,
+        };
+
+        WorldSnapshot {
+            version: SNAPSHOT_VERSION,
+            tick: self.clock.tick,
+            rng: self.rng.0.clone(),
+            boids: self.boids_query.iter().map(boid_snapshot).collect(),
+            predators: self
+                .predators_query
+                .iter()
+                .map(|boid| PredatorSnapshot {
+                    boid: boid_snapshot(boid),
+                })
+                .collect(),
+            consumables: self
+                .consumables_query
+                .iter()
+                .map(|(t, c, ts)| ConsumableSnapshot {
+                    kind: if c.is_food() {
+                        ConsumableKind::Food
+                    } else {
+                        ConsumableKind::Poison
+                    },
+                    pos: (t.translation.x, t.translation.y),
+                    nutrition: c.0,
+                    last_replication_tick: ts.0,
+                })
+                .collect(),
+            stats: self.stats.clone(),
+            ancestry: self.ancestry.clone(),
+            species: self.species.clone(),
+            innovations: self.innovations.clone(),
+            scenario_fired: self
+                .scenario
+                .as_ref()
+                .map(|s| s.fired.clone())
+                .unwrap_or_default(),
+        }
+    }
+}
+
+impl WorldSnapshot {
+    pub fn load(path: &Path) -> Result<Self, SnapshotError> {
+        let contents = fs::read_to_string(path).map_err(SnapshotError::Io)?;
+        let snapshot: Self = ron::from_str(&contents).map_err(SnapshotError::Parse)?;
+        if snapshot.version != SNAPSHOT_VERSION {
+            return Err(SnapshotError::Version(snapshot.version));
+        }
+
+        Ok(snapshot)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), SnapshotError> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir).map_err(SnapshotError::Io)?;
+        }
+        let contents = ron::to_string(self).map_err(SnapshotError::Serialize)?;
+
+        // Write to a temp file first, so a run killed mid-save never leaves a truncated snapshot
+        let tmp_path = path.with_extension("ron.tmp");
+        fs::write(&tmp_path, contents).map_err(SnapshotError::Io)?;
+        fs::rename(&tmp_path, path).map_err(SnapshotError::Io)
+    }
+}
+
+impl fmt::Display for SnapshotError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SnapshotError::Io(e) => write!(f, "{}", e),
+            SnapshotError::Parse(e) => write!(f, "failed to parse snapshot: {}", e),
+            SnapshotError::Serialize(e) => write!(f, "failed to serialize snapshot: {}", e),
+            SnapshotError::Version(v) => write!(
+                f,
+                "unsupported snapshot version {}, expected {}",
+                v, SNAPSHOT_VERSION
+            ),
+        }
+    }
+}
+
+impl std::error::Error for SnapshotError {}
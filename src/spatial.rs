@@ -0,0 +1,414 @@
+use bevy::prelude::*;
+use bevy::tasks::{ComputeTaskPool, ParallelSlice};
+
+use crate::boid::{Boid, Predator};
+use crate::elements::{Consumable, Food};
+use crate::utils::{get_world_bounds, wrapped_pos};
+use crate::*;
+
+pub struct SpatialPlugin;
+
+/// Uniform grid over the world, items outside the bounds are kept in the edge cells.
+/// Items keep the index they were inserted at, which lets callers break ties
+/// in insertion (query) order and keeps results independent of the cell layout.
+/// On a torus the cells wrap around and positions are seen across the edges.
+pub struct SpatialGrid<T> {
+    origin: Vec2,
+    size: Vec2,
+    cell_size: Vec2,
+    cols: usize,
+    rows: usize,
+    wrap: bool,
+    cells: Vec<Vec<usize>>,
+    items: Vec<GridItem<T>>,
+}
+
+pub struct GridItem<T> {
+    pub entity: Entity,
+    pub pos: Vec2,
+    pub tag: T,
+    removed: bool,
+}
+
+/// Item handed out by `SpatialGrid::claim_first_within`
+pub struct GridClaim {
+    pub entity: Entity,
+    pub pos: Vec2,
+}
+
+/// Agents tagged with whether they are predators, consumables with whether they are food
+#[derive(Resource, Default)]
+pub struct SpatialIndex {
+    pub agents: SpatialGrid<bool>,
+    pub consumables: SpatialGrid<bool>,
+}
+
+impl Plugin for SpatialPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<SpatialIndex>();
+    }
+}
+
+pub fn rebuild_agent_grid(
+    config: Res<SimConfig>,
+    mut index: ResMut<SpatialIndex>,
+    agents_query: Query<(Entity, &Transform, Has<Predator>), With<Boid>>,
+) {
+    index.agents.rebuild(
+        &config,
+        agents_query
+            .iter()
+            .map(|(e, t, is_predator)| (e, t.translation.truncate(), is_predator)),
+    );
+}
+
+pub fn rebuild_consumable_grid(
+    config: Res<SimConfig>,
+    mut index: ResMut<SpatialIndex>,
+    consumables_query: Query<(Entity, &Transform, Has<Food>), With<Consumable>>,
+) {
+    index.consumables.rebuild(
+        &config,
+        consumables_query
+            .iter()
+            .map(|(e, t, is_food)| (e, t.translation.truncate(), is_food)),
+    );
+}
+
+impl<T> Default for SpatialGrid<T> {
+    fn default() -> Self {
+        Self {
+            origin: Vec2::ZERO,
+            size: Vec2::ONE,
+            cell_size: Vec2::ONE,
+            cols: 1,
+            rows: 1,
+            wrap: false,
+            cells: Vec::new(),
+            items: Vec::new(),
+        }
+    }
+}
+
+impl<T: Copy + Sync> SpatialGrid<T> {
+    pub fn rebuild(&mut self, config: &SimConfig, items: impl Iterator<Item = (Entity, Vec2, T)>) {
+        let (min_x, min_y, max_x, max_y) = get_world_bounds(config);
+        self.origin = Vec2::new(min_x, min_y);
+        self.size = Vec2::new(max_x - min_x, max_y - min_y);
+        self.cols = ((self.size.x / SPATIAL_CELL_SIZE).ceil() as usize).max(1);
+        self.rows = ((self.size.y / SPATIAL_CELL_SIZE).ceil() as usize).max(1);
+        // Cells evenly divide the world, so wrapped cell ranges line up with the edges
+        self.cell_size = self.size / Vec2::new(self.cols as f32, self.rows as f32);
+        self.wrap = config.topology == Topology::Torus;
+
+        // Keep the cell allocations around, the grid is rebuilt several times a tick
+        self.cells.resize_with(self.cols * self.rows, Vec::new);
+        self.cells.truncate(self.cols * self.rows);
+        self.cells.iter_mut().for_each(|c| c.clear());
+        self.items.clear();
+
+        for (entity, pos, tag) in items {
+            let cell = self.cell_index(pos);
+            self.cells[cell].push(self.items.len());
+            self.items.push(GridItem {
+                entity,
+                pos,
+                tag,
+                removed: false,
+            });
+        }
+    }
+
+    /// Items in the cells overlapping the square around `pos`, callers still check the distance.
+    /// Each item comes with its position as seen from `pos`, which differs from `item.pos`
+    /// only when it's closer across a wrapped edge.
+    pub fn candidates(
+        &self,
+        pos: Vec2,
+        radius: f32,
+    ) -> impl Iterator<Item = (usize, &GridItem<T>, Vec2)> + '_ {
+        let (min_col, max_col) = self.cell_range(pos.x - self.origin.x, radius, 0);
+        let (min_row, max_row) = self.cell_range(pos.y - self.origin.y, radius, 1);
+        let (cols, rows) = (self.cols as i64, self.rows as i64);
+
+        (min_row..=max_row)
+            .flat_map(move |row| {
+                (min_col..=max_col)
+                    .map(move |col| (row.rem_euclid(rows) * cols + col.rem_euclid(cols)) as usize)
+            })
+            .flat_map(move |cell| self.cells[cell].iter())
+            .map(move |&i| (i, &self.items[i]))
+            .filter(|(_, item)| !item.removed)
+            .map(move |(i, item)| (i, item, self.seen_from(pos, item.pos)))
+    }
+
+    /// Position of the closest item within `radius` that passes `filter`, which gets the tag
+    /// and the position as seen from `pos`, and is only asked about items that would be closer.
+    /// On equal distances the last inserted item wins, same as a linear scan with `<=`.
+    pub fn closest(
+        &self,
+        pos: Vec2,
+        radius: f32,
+        filter: impl Fn(T, Vec2) -> bool,
+    ) -> Option<Vec2> {
+        let mut closest = None;
+        let mut closest_dist = f32::INFINITY;
+        let mut closest_index = 0;
+
+        for (i, item, item_pos) in self.candidates(pos, radius) {
+            let dist = item_pos.distance_squared(pos);
+            if dist > radius * radius {
+                continue;
+            }
+            let is_closer = dist < closest_dist || (dist == closest_dist && i > closest_index);
+            if is_closer && filter(item.tag, item_pos) {
+                closest = Some(item_pos);
+                closest_dist = dist;
+                closest_index = i;
+            }
+        }
+
+        closest
+    }
+
+    /// First inserted matching item strictly closer than `radius`
+    pub fn first_within(
+        &self,
+        pos: Vec2,
+        radius: f32,
+        filter: impl Fn(T) -> bool,
+    ) -> Option<(usize, &GridItem<T>)> {
+        self.candidates(pos, radius)
+            .filter(|(_, item, _)| filter(item.tag))
+            .filter(|(_, _, item_pos)| item_pos.distance_squared(pos) < radius * radius)
+            .map(|(i, item, _)| (i, item))
+            .min_by_key(|(i, _)| *i)
+    }
+
+    /// Gives every seeker the first matching item strictly closer than `radius`, each item going
+    /// to at most one seeker, and removes the claimed items.
+    /// Searches run in parallel, then the claims are settled in seeker order and a seeker whose
+    /// item was already taken searches again, so the outcome is the same as a sequential pass.
+    pub fn claim_first_within(
+        &mut self,
+        seekers: &[Vec2],
+        radius: f32,
+        filter: impl Fn(T) -> bool + Send + Sync,
+    ) -> Vec<Option<GridClaim>> {
+        let grid = &*self;
+        let filter = &filter;
+        let claims: Vec<Option<usize>> = seekers
+            .par_splat_map(ComputeTaskPool::get(), None, |chunk| {
+                chunk
+                    .iter()
+                    .map(|pos| grid.first_within(*pos, radius, filter).map(|(i, _)| i))
+                    .collect::<Vec<_>>()
+            })
+            .into_iter()
+            .flatten()
+            .collect();
+
+        seekers
+            .iter()
+            .zip(claims)
+            .map(|(pos, claim)| {
+                let i = match claim {
+                    Some(i) if self.items[i].removed => {
+                        self.first_within(*pos, radius, filter).map(|(i, _)| i)
+                    }
+                    claim => claim,
+                }?;
+                self.remove(i);
+                Some(GridClaim {
+                    entity: self.items[i].entity,
+                    pos: self.items[i].pos,
+                })
+            })
+            .collect()
+    }
+
+    /// Every item in insertion order, including removed ones
+    pub fn items(&self) -> &[GridItem<T>] {
+        &self.items
+    }
+
+    /// Drops an item from all further queries until the next rebuild
+    pub fn remove(&mut self, index: usize) {
+        self.items[index].removed = true;
+    }
+
+    /// Nearest copy of `other` as seen from `pos`
+    fn seen_from(&self, pos: Vec2, other: Vec2) -> Vec2 {
+        if !self.wrap {
+            return other;
+        }
+        wrapped_pos(pos, other, self.size)
+    }
+
+    /// Unwrapped cell range along `axis` covering `offset` ± `radius`
+    fn cell_range(&self, offset: f32, radius: f32, axis: usize) -> (i64, i64) {
+        let num_cells = if axis == 0 { self.cols } else { self.rows } as i64;
+        let min = ((offset - radius) / self.cell_size[axis]).floor() as i64;
+        let max = ((offset + radius) / self.cell_size[axis]).floor() as i64;
+
+        if !self.wrap {
+            (min.clamp(0, num_cells - 1), max.clamp(0, num_cells - 1))
+        } else if max.saturating_sub(min) + 1 >= num_cells {
+            (0, num_cells - 1)
+        } else {
+            (min, max)
+        }
+    }
+
+    fn cell_index(&self, pos: Vec2) -> usize {
+        let (min_col, _) = self.cell_range(pos.x - self.origin.x, 0.0, 0);
+        let (min_row, _) = self.cell_range(pos.y - self.origin.y, 0.0, 1);
+        let (cols, rows) = (self.cols as i64, self.rows as i64);
+        (min_row.rem_euclid(rows) * cols + min_col.rem_euclid(cols)) as usize
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::tasks::TaskPool;
+
+    use super::*;
+
+    fn grid(topology: Topology, items: &[(f32, f32)]) -> SpatialGrid<()> {
+        let config = SimConfig {
+            world_w: 100.0,
+            world_h: 100.0,
+            topology,
+            ..default()
+        };
+        let mut grid = SpatialGrid::default();
+        grid.rebuild(
+            &config,
+            items
+                .iter()
+                .enumerate()
+                .map(|(i, pos)| (Entity::from_raw(i as u32), Vec2::from(*pos), ())),
+        );
+        grid
+    }
+
+    fn claimed(claims: &[Option<GridClaim>]) -> Vec<Option<u32>> {
+        claims
+            .iter()
+            .map(|c| c.as_ref().map(|c| c.entity.index()))
+            .collect()
+    }
+
+    fn claim(grid: &mut SpatialGrid<()>, seekers: &[(f32, f32)], radius: f32) -> Vec<Option<u32>> {
+        ComputeTaskPool::get_or_init(TaskPool::default);
+        let seekers: Vec<Vec2> = seekers.iter().map(|p| Vec2::from(*p)).collect();
+        claimed(&grid.claim_first_within(&seekers, radius, |_| true))
+    }
+
+    #[test]
+    fn competing_seekers_resolve_in_seeker_order() {
+        let mut g = grid(Topology::Walls, &[(0.0, 0.0)]);
+        assert_eq!(
+            claim(&mut g, &[(5.0, 0.0), (-5.0, 0.0)], 10.0),
+            [Some(0), None]
+        );
+        let mut g = grid(Topology::Walls, &[(0.0, 0.0)]);
+        assert_eq!(
+            claim(&mut g, &[(-5.0, 0.0), (5.0, 0.0)], 10.0),
+            [Some(0), None]
+        );
+        // Claimed items are gone for later queries
+        assert!(g.first_within(Vec2::ZERO, 10.0, |_| true).is_none());
+    }
+
+    #[test]
+    fn taken_claims_fall_back_to_the_next_item() {
+        // Both seekers first find item 0, the second one then takes item 1
+        let mut g = grid(Topology::Walls, &[(0.0, 0.0), (8.0, 0.0), (40.0, 0.0)]);
+        assert_eq!(
+            claim(&mut g, &[(-5.0, 0.0), (3.0, 0.0), (4.0, 0.0)], 10.0),
+            [Some(0), Some(1), None]
+        );
+    }
+
+    #[test]
+    fn claims_match_a_sequential_pass() {
+        let items: Vec<(f32, f32)> = (0..200)
+            .map(|i| {
+                (
+                    ((i * 37) % 200) as f32 - 100.0,
+                    ((i * 53) % 200) as f32 - 100.0,
+                )
+            })
+            .collect();
+        let seekers: Vec<(f32, f32)> = (0..300)
+            .map(|i| {
+                (
+                    ((i * 71) % 200) as f32 - 100.0,
+                    ((i * 29) % 200) as f32 - 100.0,
+                )
+            })
+            .collect();
+
+        let mut g = grid(Topology::Torus, &items);
+        let claims = claim(&mut g, &seekers, 15.0);
+        let mut sequential = grid(Topology::Torus, &items);
+        let expected: Vec<Option<u32>> = seekers
+            .iter()
+            .map(|pos| {
+                let (i, item) = sequential.first_within(Vec2::from(*pos), 15.0, |_| true)?;
+                let entity = item.entity.index();
+                sequential.remove(i);
+                Some(entity)
+            })
+            .collect();
+        assert_eq!(claims, expected);
+
+        let mut entities: Vec<u32> = claims.iter().flatten().copied().collect();
+        let num_claims = entities.len();
+        assert!(num_claims > 0);
+        entities.sort_unstable();
+        entities.dedup();
+        assert_eq!(entities.len(), num_claims);
+        assert_eq!(g.items().iter().filter(|i| i.removed).count(), num_claims);
+    }
+
+    #[test]
+    fn torus_queries_see_across_the_edges() {
+        let g = grid(Topology::Torus, &[(-97.0, -99.0)]);
+        let seen = g.closest(Vec2::new(98.0, 99.0), 10.0, |_, _| true);
+        assert_eq!(seen, Some(Vec2::new(103.0, 101.0)));
+        assert_eq!(g.cell_range(195.0, 10.0, 0), (3, 4));
+
+        let g = grid(Topology::Soft, &[(-97.0, -99.0)]);
+        assert!(g
+            .closest(Vec2::new(98.0, 99.0), 10.0, |_, _| true)
+            .is_none());
+    }
+
+    #[test]
+    fn radius_wider_than_the_world_sees_each_item_once() {
+        let items = [(-90.0, -90.0), (0.0, 0.0), (60.0, -20.0), (99.0, 99.0)];
+        for topology in [Topology::Torus, Topology::Walls] {
+            let g = grid(topology, &items);
+            let mut seen: Vec<usize> = g.candidates(Vec2::ZERO, 500.0).map(|(i, ..)| i).collect();
+            seen.sort_unstable();
+            assert_eq!(seen, [0, 1, 2, 3]);
+        }
+        let g = grid(Topology::Torus, &items);
+        assert_eq!(g.cell_range(100.0, 500.0, 0), (0, 3));
+    }
+
+    #[test]
+    fn items_outside_the_bounds_stay_in_the_edge_cells() {
+        let g = grid(Topology::Walls, &[(500.0, -500.0)]);
+        assert_eq!(g.cell_index(Vec2::new(500.0, -500.0)), 3);
+        assert_eq!(g.cell_range(600.0, 1.0, 0), (3, 3));
+        assert_eq!(g.cell_range(-400.0, 1.0, 1), (0, 0));
+        let found = g.first_within(Vec2::new(499.0, -499.0), 5.0, |_| true);
+        assert_eq!(found.map(|(i, _)| i), Some(0));
+        assert!(g
+            .closest(Vec2::new(95.0, -95.0), 10.0, |_, _| true)
+            .is_none());
+    }
+}
@@ -0,0 +1,144 @@
+use std::collections::BTreeMap;
+
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::boid::{Boid, Predator};
+use crate::brain::gene_specs;
+use crate::clock::{on_config_timer, SimClock};
+use crate::genome::Genome;
+use crate::lineage::Lineage;
+use crate::stats::update_stats;
+use crate::*;
+
+pub struct SpeciesPlugin;
+
+/// Species the agent was put in by the last clustering pass, `None` until the first one after its birth
+#[derive(Component, Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Species(pub Option<u64>);
+
+/// Every species that ever existed, extinct ones included
+#[derive(Resource, Debug, Clone, Default, Serialize, Deserialize)]
+pub struct SpeciesRegistry {
+    next_id: u64,
+    pub species: BTreeMap<u64, SpeciesRecord>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SpeciesRecord {
+    pub is_predator: bool,
+    /// Average genome of the members at the last pass
+    pub representative: Genome,
+    pub num_members: usize,
+    pub birth_tick: u64,
+    pub extinction_tick: Option<u64>,
+}
+
+impl Plugin for SpeciesPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<SpeciesRegistry>().add_systems(
+            FixedUpdate,
+            cluster_species
+                .in_set(SimSet::Stats)
+                .before(update_stats)
+                .run_if(in_state(SimState::Simulating))
+                .run_if(on_config_timer(|c| c.species_interval)),
+        );
+    }
+}
+
+/// Puts every agent, oldest first, in the living species of its kind with the closest
+/// average genome within `species_threshold`, or in a new species of its own.
+/// Species left without members go extinct.
+fn cluster_species(
+    clock: Res<SimClock>,
+    config: Res<SimConfig>,
+    mut registry: ResMut<SpeciesRegistry>,
+    mut agents_query: Query<(&Genome, &Lineage, &mut Species, Has<Predator>), With<Boid>>,
+) {
+    let mut agents: Vec<_> = agents_query.iter_mut().collect();
+    agents.sort_unstable_by_key(|(_, lineage, ..)| lineage.id);
+
+    for is_predator in [false, true] {
+        // Network weights count too, so diverging brains split species
+        let specs = &gene_specs(&config, is_predator);
+        let mut representatives: Vec<(u64, Genome)> = registry
+            .living(is_predator)
+            .map(|(id, s)| (id, s.representative.clone()))
+            .collect();
+        let mut members: BTreeMap<u64, Vec<&Genome>> = BTreeMap::new();
+
+        for (genome, _, species, _) in agents.iter_mut().filter(|a| a.3 == is_predator) {
+            // Ties go to the older species
+            let closest = representatives
+                .iter()
+                .map(|(id, rep)| (*id, genome.distance(rep, specs)))
+                .filter(|(_, dist)| *dist <= config.species_threshold)
+                .min_by(|a, b| a.1.total_cmp(&b.1).then(a.0.cmp(&b.0)))
+                .map(|(id, _)| id);
+            let id = match closest {
+                Some(id) => id,
+                None => {
+                    let id = registry.found(genome, is_predator, &clock);
+                    representatives.push((id, (*genome).clone()));
+                    id
+                }
+            };
+            species.set_if_neq(Species(Some(id)));
+            members.entry(id).or_default().push(*genome);
+        }
+
+        let living: Vec<u64> = registry.living(is_predator).map(|(id, _)| id).collect();
+        for id in living {
+            let record = registry.species.get_mut(&id).unwrap();
+            match members.get(&id) {
+                Some(genomes) => {
+                    record.representative = Genome::mean(genomes, specs);
+                    record.num_members = genomes.len();
+                }
+                None => {
+                    record.num_members = 0;
+                    record.extinction_tick = Some(clock.tick);
+                    debug!(
+                        "Species {} went extinct at {:.1}s",
+                        id,
+                        clock.elapsed_secs()
+                    );
+                }
+            }
+        }
+    }
+}
+
+/// Distinct hue per species, so neighbouring ids look different
+pub fn species_color(id: u64) -> Color {
+    let hue = (id as f32 * 137.508) % 360.0;
+    Color::hsl(hue, 0.7, 0.7)
+}
+
+impl SpeciesRegistry {
+    /// Species that haven't gone extinct, by id
+    pub fn living(&self, is_predator: bool) -> impl Iterator<Item = (u64, &SpeciesRecord)> {
+        self.species
+            .iter()
+            .filter(move |(_, s)| s.is_predator == is_predator && s.extinction_tick.is_none())
+            .map(|(id, s)| (*id, s))
+    }
+
+    fn found(&mut self, genome: &Genome, is_predator: bool, clock: &SimClock) -> u64 {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.species.insert(
+            id,
+            SpeciesRecord {
+                is_predator,
+                representative: genome.clone(),
+                num_members: 0,
+                birth_tick: clock.tick,
+                extinction_tick: None,
+            },
+        );
+        debug!("Species {} appeared at {:.1}s", id, clock.elapsed_secs());
+        id
+    }
+}
@@ -1,16 +1,25 @@
-use std::time::Duration;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
 
+use bevy::ecs::system::SystemParam;
 use bevy::prelude::*;
-use bevy::time::common_conditions::on_timer;
+use serde::{Deserialize, Serialize};
 
-use crate::boid::{BirthTimeStamp, Boid, Dna, Predator, PredatorDna};
+use crate::boid::{BirthTimeStamp, Predator, PreyFilter};
+use crate::brain::Brain;
+use crate::clock::{on_config_timer, SimClock};
 use crate::elements::{Food, Poison};
+use crate::genome::Genome;
+use crate::lineage::Lineage;
+use crate::season::SeasonCycle;
+use crate::species::SpeciesRegistry;
 use crate::utils::LimitedVec;
 use crate::*;
 
 pub struct StatsPlugin;
 
-#[derive(Resource)]
+#[derive(Resource, Clone, Serialize, Deserialize)]
 pub struct SimulationStats {
     pub num_boids: LimitedVec<f32>,
     pub num_predators: LimitedVec<f32>,
@@ -18,110 +27,277 @@ pub struct SimulationStats {
     pub num_poison: LimitedVec<f32>,
     pub avg_lifespan: LimitedVec<f32>,
     pub avg_predator_lifespan: LimitedVec<f32>,
-    pub food_perception: LimitedVec<f32>,
-    pub poison_perception: LimitedVec<f32>,
-    pub predator_perception: LimitedVec<f32>,
-    pub prey_perception: LimitedVec<f32>,
-    pub speed: LimitedVec<f32>,
-    pub predator_speed: LimitedVec<f32>,
-    pub food_affinity: LimitedVec<f32>,
-    pub poison_affinity: LimitedVec<f32>,
-    pub predator_affinity: LimitedVec<f32>,
-    pub prey_affinity: LimitedVec<f32>,
-    pub steering_force: LimitedVec<f32>,
+    /// Generations since the founders, 0 for a founder
+    pub avg_generation: LimitedVec<f32>,
+    pub max_generation: LimitedVec<f32>,
+    pub avg_predator_generation: LimitedVec<f32>,
+    pub max_predator_generation: LimitedVec<f32>,
+    /// Living species, counts rather than fractions
+    pub num_species: LimitedVec<f32>,
+    pub num_predator_species: LimitedVec<f32>,
+    /// Size of the steering networks, 0 with the `Pull` controller
+    pub avg_hidden_nodes: LimitedVec<f32>,
+    pub avg_connections: LimitedVec<f32>,
+    pub avg_predator_hidden_nodes: LimitedVec<f32>,
+    pub avg_predator_connections: LimitedVec<f32>,
+    /// Population average of every gene, named by `gene_series_name`
+    pub genes: Vec<(String, LimitedVec<f32>)>,
+    /// Season index, 0 for spring to 3 for winter, -1 without seasons
+    pub season: LimitedVec<f32>,
+    /// 1 during the day, 0 at night
+    pub daylight: LimitedVec<f32>,
+    pub markers: Vec<StatMarker>,
+    pub num_samples: usize,
+}
+
+/// Annotation of an event that happened during the run, e.g. a config change
+#[derive(Clone, Serialize, Deserialize)]
+pub struct StatMarker {
+    pub sample: usize,
+    pub time: f32,
+    pub label: String,
 }
 
 impl Plugin for StatsPlugin {
     fn build(&self, app: &mut App) {
         app.insert_resource(SimulationStats::new()).add_systems(
-            Update,
+            FixedUpdate,
             update_stats
+                .in_set(SimSet::Stats)
                 .run_if(in_state(SimState::Simulating))
-                .run_if(on_timer(Duration::from_secs_f32(STAT_COLLECTION_RATE))),
+                .run_if(on_config_timer(|c| c.stat_collection_rate)),
         );
     }
 }
 
-fn update_stats(
+type AgentStatsQuery = (
+    &'static Genome,
+    &'static BirthTimeStamp,
+    &'static Lineage,
+    &'static Brain,
+);
+
+/// Agents and consumables `update_stats` counts and averages over
+#[derive(SystemParam)]
+pub struct Population<'w, 's> {
+    boid_query: Query<'w, 's, AgentStatsQuery, PreyFilter>,
+    predators_query: Query<'w, 's, AgentStatsQuery, With<Predator>>,
+    food_query: Query<'w, 's, With<Food>>,
+    poison_query: Query<'w, 's, With<Poison>>,
+}
+
+pub fn update_stats(
     mut stats: ResMut<SimulationStats>,
-    boid_query: Query<(&Dna, &BirthTimeStamp), (With<Boid>, Without<Predator>)>,
-    predators_query: Query<(&Dna, &PredatorDna, &BirthTimeStamp), With<Predator>>,
-    food_query: Query<With<Food>>,
-    poison_query: Query<With<Poison>>,
+    clock: Res<SimClock>,
+    config: Res<SimConfig>,
+    cycle: Res<SeasonCycle>,
+    species: Res<SpeciesRegistry>,
+    population: Population,
 ) {
+    let Population {
+        boid_query,
+        predators_query,
+        food_query,
+        poison_query,
+    } = population;
     let num_boids = boid_query.iter().len() as f32;
     let num_predators = predators_query.iter().len() as f32;
     let num_food = food_query.iter().len() as f32;
     let num_poison = poison_query.iter().len() as f32;
 
-    let mut food_perception_radius = 0.0;
-    let mut poison_perception_radius = 0.0;
-    let mut predator_perception_radius = 0.0;
-    let mut food_affinity = 0.0;
-    let mut poison_affinity = 0.0;
-    let mut predator_affinity = 0.0;
-    let mut speed = 0.0;
-    let mut steering_force = 0.0;
-    let mut avg_lifespan = 0.0;
-
-    let mut avg_predator_lifespan = 0.0;
-    let mut predator_speed = 0.0;
-    let mut predator_boid_affinity = 0.0;
-    let mut prey_perception_radius = 0.0;
-
-    for (dna, birth_ts) in boid_query.iter() {
-        food_perception_radius += dna.food_perception_radius;
-        poison_perception_radius += dna.poison_perception_radius;
-        predator_perception_radius += dna.predator_perception_radius;
-        food_affinity += dna.food_pull;
-        poison_affinity += dna.poison_pull;
-        predator_affinity += dna.predator_pull;
-        speed += dna.speed;
-        steering_force += dna.steering_force;
-        avg_lifespan += birth_ts.0.elapsed().as_secs_f32();
-    }
+    let avg_lifespan: f32 = boid_query
+        .iter()
+        .map(|(_, birth_ts, ..)| clock.secs_since(birth_ts.0))
+        .sum();
+    let avg_predator_lifespan: f32 = predators_query
+        .iter()
+        .map(|(_, birth_ts, ..)| clock.secs_since(birth_ts.0))
+        .sum();
 
-    for (dna, pred_dna, birth_ts) in predators_query.iter() {
-        avg_predator_lifespan += birth_ts.0.elapsed().as_secs_f32();
-        predator_speed += dna.speed;
-        predator_boid_affinity += pred_dna.prey_pull;
-        prey_perception_radius += pred_dna.prey_perception;
+    let boid_generations: Vec<f32> = boid_query
+        .iter()
+        .map(|(_, _, lineage, _)| lineage.generation as f32)
+        .collect();
+    let predator_generations: Vec<f32> = predators_query
+        .iter()
+        .map(|(_, _, lineage, _)| lineage.generation as f32)
+        .collect();
+    let avg_generation: f32 = boid_generations.iter().sum();
+    let avg_predator_generation: f32 = predator_generations.iter().sum();
+    // NaN without agents, like the averages
+    let max_generation = boid_generations.iter().copied().fold(f32::NAN, f32::max);
+    let max_predator_generation = predator_generations
+        .iter()
+        .copied()
+        .fold(f32::NAN, f32::max);
+
+    let (hidden_nodes, connections) = boid_query
+        .iter()
+        .map(|(.., brain)| brain.complexity())
+        .fold((0, 0), |a, b| (a.0 + b.0, a.1 + b.1));
+    let (predator_hidden_nodes, predator_connections) = predators_query
+        .iter()
+        .map(|(.., brain)| brain.complexity())
+        .fold((0, 0), |a, b| (a.0 + b.0, a.1 + b.1));
+
+    let mut gene_means = Vec::new();
+    for spec in config.boid_genes.iter() {
+        let sum: f32 = boid_query.iter().map(|(g, ..)| g.get(&spec.name)).sum();
+        gene_means.push((gene_series_name(spec, false), sum / num_boids));
+    }
+    for spec in config.predator_genes.iter() {
+        let sum: f32 = predators_query
+            .iter()
+            .map(|(g, ..)| g.get(&spec.name))
+            .sum();
+        gene_means.push((gene_series_name(spec, true), sum / num_predators));
     }
+    stats.push_genes(gene_means);
 
-    stats.num_boids.push(num_boids / NUM_BOIDS as f32);
+    stats.num_boids.push(num_boids / config.num_boids as f32);
     stats
         .num_predators
-        .push(num_predators / NUM_PREDATORS as f32);
-    stats.num_food.push(num_food / NUM_FOOD as f32);
-    stats.num_poison.push(num_poison / NUM_POISON as f32);
+        .push(num_predators / config.num_predators as f32);
+    stats.num_food.push(num_food / config.num_food as f32);
+    stats.num_poison.push(num_poison / config.num_poison as f32);
+    stats.avg_lifespan.push(avg_lifespan / num_boids);
     stats
-        .food_perception
-        .push(food_perception_radius / num_boids);
+        .avg_predator_lifespan
+        .push(avg_predator_lifespan / num_predators);
+    stats.avg_generation.push(avg_generation / num_boids);
+    stats.max_generation.push(max_generation);
     stats
-        .poison_perception
-        .push(poison_perception_radius / num_boids);
+        .avg_predator_generation
+        .push(avg_predator_generation / num_predators);
+    stats.max_predator_generation.push(max_predator_generation);
+    stats.num_species.push(species.living(false).count() as f32);
     stats
-        .predator_perception
-        .push(predator_perception_radius / num_boids);
+        .num_predator_species
+        .push(species.living(true).count() as f32);
+    stats.avg_hidden_nodes.push(hidden_nodes as f32 / num_boids);
+    stats.avg_connections.push(connections as f32 / num_boids);
     stats
-        .prey_perception
-        .push(prey_perception_radius / num_predators);
-    stats.food_affinity.push(food_affinity / num_boids);
-    stats.steering_force.push(steering_force / num_boids);
-    stats.poison_affinity.push(poison_affinity / num_boids);
-    stats.predator_affinity.push(predator_affinity / num_boids);
+        .avg_predator_hidden_nodes
+        .push(predator_hidden_nodes as f32 / num_predators);
     stats
-        .prey_affinity
-        .push(predator_boid_affinity / num_predators);
-    stats.speed.push(speed / num_boids);
-    stats.predator_speed.push(predator_speed / num_predators);
-    stats.avg_lifespan.push(avg_lifespan / num_boids);
+        .avg_predator_connections
+        .push(predator_connections as f32 / num_predators);
     stats
-        .avg_predator_lifespan
-        .push(avg_predator_lifespan / num_predators);
+        .season
+        .push(cycle.season.map_or(-1.0, |s| s.index() as f32));
+    stats.daylight.push(if cycle.is_night { 0.0 } else { 1.0 });
+    stats.num_samples += 1;
 }
 
 impl SimulationStats {
+    /// Marks the upcoming sample with an event
+    pub fn add_marker(&mut self, time: f32, label: String) {
+        self.markers.push(StatMarker {
+            sample: self.num_samples,
+            time,
+            label,
+        });
+    }
+
+    /// Plot x positions of the markers still within the recorded window
+    pub fn marker_positions(&self) -> Vec<f64> {
+        let first_sample = self.num_samples - self.num_boids.items.len();
+        self.markers
+            .iter()
+            .filter(|m| m.sample >= first_sample)
+            .map(|m| (m.sample - first_sample) as f64)
+            .collect()
+    }
+
+    /// Every recorded series along with its name
+    pub fn series(&self) -> Vec<(&str, &LimitedVec<f32>)> {
+        let mut series = vec![
+            ("num_boids", &self.num_boids),
+            ("num_predators", &self.num_predators),
+            ("num_food", &self.num_food),
+            ("num_poison", &self.num_poison),
+            ("avg_lifespan", &self.avg_lifespan),
+            ("avg_predator_lifespan", &self.avg_predator_lifespan),
+            ("avg_generation", &self.avg_generation),
+            ("max_generation", &self.max_generation),
+            ("avg_predator_generation", &self.avg_predator_generation),
+            ("max_predator_generation", &self.max_predator_generation),
+            ("num_species", &self.num_species),
+            ("num_predator_species", &self.num_predator_species),
+            ("avg_hidden_nodes", &self.avg_hidden_nodes),
+            ("avg_connections", &self.avg_connections),
+            ("avg_predator_hidden_nodes", &self.avg_predator_hidden_nodes),
+            ("avg_predator_connections", &self.avg_predator_connections),
+            ("season", &self.season),
+            ("daylight", &self.daylight),
+        ];
+        series.extend(self.genes.iter().map(|(name, s)| (name.as_str(), s)));
+        series
+    }
+
+    pub fn gene(&self, name: &str) -> Option<&LimitedVec<f32>> {
+        self.genes.iter().find(|(n, _)| n == name).map(|(_, s)| s)
+    }
+
+    /// Adds a sample to every gene series, genes no longer in the config get NaN
+    /// and new ones are padded with NaN for the samples before they existed
+    fn push_genes(&mut self, means: Vec<(String, f32)>) {
+        let num_rows = self.num_boids.items.len();
+        for (name, _) in means.iter() {
+            if self.gene(name).is_none() {
+                let mut series = LimitedVec::new();
+                for _ in 0..num_rows {
+                    series.push(f32::NAN);
+                }
+                self.genes.push((name.clone(), series));
+            }
+        }
+
+        for (name, series) in self.genes.iter_mut() {
+            let mean = means
+                .iter()
+                .find(|(n, _)| n == name)
+                .map_or(f32::NAN, |(_, mean)| *mean);
+            series.push(mean);
+        }
+    }
+
+    /// Writes all series as csv columns, one row per stat collection
+    pub fn write_csv(&self, path: &Path) -> io::Result<()> {
+        let series = self.series();
+        let mut writer = BufWriter::new(File::create(path)?);
+
+        let header: Vec<&str> = series.iter().map(|(name, _)| *name).collect();
+        writeln!(writer, "{}", header.join(","))?;
+
+        let num_rows = series.iter().map(|(_, s)| s.items.len()).max().unwrap_or(0);
+        for row in 0..num_rows {
+            let values: Vec<String> = series
+                .iter()
+                .map(|(_, s)| s.items.get(row).map(|v| v.to_string()).unwrap_or_default())
+                .collect();
+            writeln!(writer, "{}", values.join(","))?;
+        }
+
+        writer.flush()
+    }
+
+    pub fn write_markers_csv(&self, path: &Path) -> io::Result<()> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        writeln!(writer, "sample,time,label")?;
+        for m in self.markers.iter() {
+            writeln!(
+                writer,
+                "{},{},\"{}\"",
+                m.sample,
+                m.time,
+                m.label.replace('"', "\"\"")
+            )?;
+        }
+
+        writer.flush()
+    }
+
     fn new() -> Self {
         Self {
             num_boids: LimitedVec::new(),
@@ -130,17 +306,30 @@ impl SimulationStats {
             num_poison: LimitedVec::new(),
             avg_lifespan: LimitedVec::new(),
             avg_predator_lifespan: LimitedVec::new(),
-            food_perception: LimitedVec::new(),
-            poison_perception: LimitedVec::new(),
-            predator_perception: LimitedVec::new(),
-            prey_perception: LimitedVec::new(),
-            speed: LimitedVec::new(),
-            predator_speed: LimitedVec::new(),
-            food_affinity: LimitedVec::new(),
-            poison_affinity: LimitedVec::new(),
-            predator_affinity: LimitedVec::new(),
-            prey_affinity: LimitedVec::new(),
-            steering_force: LimitedVec::new(),
+            avg_generation: LimitedVec::new(),
+            max_generation: LimitedVec::new(),
+            avg_predator_generation: LimitedVec::new(),
+            max_predator_generation: LimitedVec::new(),
+            num_species: LimitedVec::new(),
+            num_predator_species: LimitedVec::new(),
+            avg_hidden_nodes: LimitedVec::new(),
+            avg_connections: LimitedVec::new(),
+            avg_predator_hidden_nodes: LimitedVec::new(),
+            avg_predator_connections: LimitedVec::new(),
+            genes: Vec::new(),
+            season: LimitedVec::new(),
+            daylight: LimitedVec::new(),
+            markers: Vec::new(),
+            num_samples: 0,
         }
     }
 }
+
+/// Stats column of a gene, predator genes are prefixed with `predator_`
+pub fn gene_series_name(spec: &GeneSpec, is_predator: bool) -> String {
+    if is_predator {
+        format!("predator_{}", spec.name)
+    } else {
+        spec.name.to_string()
+    }
+}
@@ -6,27 +6,36 @@ use bevy::{
 };
 use bevy_egui::egui::Color32;
 use rand::Rng;
+use serde::{Deserialize, Serialize};
 
 use crate::*;
 
+#[derive(Clone, Serialize, Deserialize)]
 pub struct LimitedVec<T> {
     pub items: Vec<T>,
     pub max_size: usize,
 }
 
-pub fn get_rand_unit_vec3() -> Vec3 {
-    let mut rng = rand::thread_rng();
+pub fn get_rand_unit_vec3(rng: &mut impl Rng) -> Vec3 {
     let x = rng.gen_range(-100.0..100.0);
     let y = rng.gen_range(-100.0..100.0);
 
     vec3(x, y, 0.0).normalize()
 }
 
-pub fn get_rand_unit_vec2() -> Vec2 {
-    let rand_vec3 = get_rand_unit_vec3();
+pub fn get_rand_unit_vec2(rng: &mut impl Rng) -> Vec2 {
+    let rand_vec3 = get_rand_unit_vec3(rng);
     vec2(rand_vec3.x, rand_vec3.y)
 }
 
+/// Standard normal sample, Box-Muller
+pub fn get_rand_normal(rng: &mut impl Rng) -> f32 {
+    let u1: f32 = rng.gen_range(f32::EPSILON..1.0);
+    let u2: f32 = rng.gen_range(0.0..1.0);
+
+    (-2.0 * u1.ln()).sqrt() * (2.0 * PI * u2).cos()
+}
+
 pub fn calc_rotation_angle(v1: Vec3, v2: Vec3) -> f32 {
     let dx = v1.x - v2.x;
     let dy = v1.y - v2.y;
@@ -39,21 +48,61 @@ pub fn calc_rotation_angle(v1: Vec3, v2: Vec3) -> f32 {
     angle_rad
 }
 
-pub fn get_world_bounds() -> (f32, f32, f32, f32) {
-    let min_x = -WORLD_W;
-    let min_y = -WORLD_H;
-    let max_x = WORLD_W;
-    let max_y = WORLD_H;
+pub fn get_world_bounds(config: &SimConfig) -> (f32, f32, f32, f32) {
+    let min_x = -config.world_w;
+    let min_y = -config.world_h;
+    let max_x = config.world_w;
+    let max_y = config.world_h;
 
     (min_x, min_y, max_x, max_y)
 }
 
-pub fn limit_to_world((x, y): (f32, f32)) -> (f32, f32) {
-    let (x, y) = (x.min(WORLD_W), y.min(WORLD_H));
-    let (x, y) = (x.max(-WORLD_W), y.max(-WORLD_H));
+/// Brings a position back inside the world, wrapping around on a torus and clamping otherwise
+pub fn limit_to_world((x, y): (f32, f32), config: &SimConfig) -> (f32, f32) {
+    if config.topology == Topology::Torus {
+        let (w, h) = (config.world_w, config.world_h);
+        return (
+            (x + w).rem_euclid(2.0 * w) - w,
+            (y + h).rem_euclid(2.0 * h) - h,
+        );
+    }
+
+    let (x, y) = (x.min(config.world_w), y.min(config.world_h));
+    let (x, y) = (x.max(-config.world_w), y.max(-config.world_h));
     (x, y)
 }
 
+/// Copy of `other` closest to `pos` in a world of `size` that wraps around its edges
+pub fn wrapped_pos(pos: Vec2, other: Vec2, size: Vec2) -> Vec2 {
+    let delta = other - pos;
+    pos + delta - size * (delta / size).round()
+}
+
+/// Squared distance between two positions, measured across the edges on a torus
+pub fn world_distance_squared(pos: Vec2, other: Vec2, config: &SimConfig) -> f32 {
+    if config.topology != Topology::Torus {
+        return pos.distance_squared(other);
+    }
+    let size = 2.0 * vec2(config.world_w, config.world_h);
+    wrapped_pos(pos, other, size).distance_squared(pos)
+}
+
+/// Mirrors a position that went past a world edge back inside, turning the velocity away from it
+pub fn reflect_off_walls(pos: &mut Vec2, velocity: &mut Vec2, config: &SimConfig) {
+    let bounds = vec2(config.world_w, config.world_h);
+    for axis in 0..2 {
+        if pos[axis] > bounds[axis] {
+            pos[axis] = 2.0 * bounds[axis] - pos[axis];
+            velocity[axis] = -velocity[axis].abs();
+        } else if pos[axis] < -bounds[axis] {
+            pos[axis] = -2.0 * bounds[axis] - pos[axis];
+            velocity[axis] = velocity[axis].abs();
+        }
+    }
+    // A boid spawned far outside can't be mirrored back in a single step
+    *pos = pos.clamp(-bounds, bounds);
+}
+
 pub fn get_steering_force(target: Vec2, pos: Vec2, velocity: Vec2) -> Vec2 {
     let desired = target - pos;
     desired - velocity
@@ -84,6 +133,12 @@ pub fn hex_to_rgba(hex: &str) -> Option<(u8, u8, u8, u8)> {
         .map(|[r, g, b, a]| (r, g, b, a))
 }
 
+impl<T> Default for LimitedVec<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl<T> LimitedVec<T> {
     pub fn new() -> Self {
         LimitedVec {
@@ -1,11 +1,13 @@
-use std::time::Duration;
-
 use bevy::prelude::*;
-use bevy::time::common_conditions::on_timer;
-use rand::Rng;
 
-use crate::boid::{Boid, BoidBundle, Predator, PredatorBundle};
+use crate::biome::{sync_biome_map, BiomeMap};
+use crate::boid::{BoidBundle, Predator, PredatorBundle, PreyFilter};
+use crate::clock::on_sim_timer;
 use crate::elements::{FoodBundle, PoisonBundle};
+use crate::lineage::{record_deaths, Ancestry};
+use crate::obstacles::{get_free_pos, sync_obstacles, Obstacle};
+use crate::scenario::resume_scenario;
+use crate::snapshot::{restore_snapshot, PendingSnapshot};
 use crate::SimState;
 use crate::*;
 
@@ -15,57 +17,149 @@ impl Plugin for WorldPlugin {
     fn build(&self, app: &mut bevy::prelude::App) {
         app.add_systems(
             OnEnter(SimState::InitSim),
-            (populate_boids, populate_consumables, start_simulation),
+            (
+                (sync_obstacles, sync_biome_map),
+                // Nothing may spawn inside the obstacles spawned above
+                apply_deferred,
+                (restore_snapshot, resume_scenario)
+                    .chain()
+                    .run_if(resource_exists::<PendingSnapshot>()),
+                (populate_boids, populate_consumables)
+                    .chain()
+                    .run_if(not(resource_exists::<PendingSnapshot>())),
+                start_simulation,
+            )
+                .chain(),
+        )
+        .configure_sets(
+            FixedUpdate,
+            (
+                SimSet::Clock,
+                SimSet::World,
+                SimSet::Scenario,
+                SimSet::Catastrophe,
+                SimSet::Boids,
+                SimSet::Elements,
+                SimSet::Stats,
+            )
+                .chain(),
+        )
+        // Spawns and despawns of scenarios and catastrophes land before the spatial index is rebuilt
+        .add_systems(
+            FixedUpdate,
+            apply_deferred
+                .after(SimSet::Catastrophe)
+                .before(SimSet::Boids),
         )
         .add_systems(
-            Update,
+            FixedUpdate,
             populate_boids
-                .run_if(on_timer(Duration::from_secs_f32(5.0)))
+                .in_set(SimSet::World)
+                .after(record_deaths)
+                .run_if(on_sim_timer(5.0))
                 .run_if(in_state(SimState::Simulating)),
         );
     }
 }
 
 fn populate_boids(
-    mut commands: Commands,
-    handle: Res<GlobalTextureHandle>,
-    boid_query: Query<(With<Boid>, Without<Predator>)>,
+    spawner: Spawner,
+    mut ancestry: ResMut<Ancestry>,
+    boid_query: Query<PreyFilter>,
     predator_query: Query<With<Predator>>,
+    obstacles_query: Query<&Obstacle>,
 ) {
+    let Spawner {
+        mut commands,
+        clock,
+        config,
+        mut rng,
+        handle,
+    } = spawner;
     let remaining_boids = if boid_query.iter().len() > 0 {
         0
     } else {
-        NUM_BOIDS
+        config.num_boids
     };
     let remaining_predators = if predator_query.iter().len() > 0 {
         0
     } else {
-        NUM_PREDATORS
+        config.num_predators
     };
 
+    let rng = &mut rng.0;
     for _ in 0..remaining_boids {
-        commands.spawn(BoidBundle::new(handle.0.clone().unwrap(), false));
+        let Some(pos) = get_free_pos(&config, rng, &obstacles_query) else {
+            warn!("No room outside the obstacles for a boid");
+            continue;
+        };
+        commands.spawn(BoidBundle::new(
+            &mut ancestry,
+            pos.into(),
+            handle.atlas(),
+            false,
+            clock.tick,
+            &config,
+            rng,
+        ));
     }
     for _ in 0..remaining_predators {
-        commands.spawn(PredatorBundle::new(handle.0.clone().unwrap()));
+        let Some(pos) = get_free_pos(&config, rng, &obstacles_query) else {
+            warn!("No room outside the obstacles for a predator");
+            continue;
+        };
+        commands.spawn(PredatorBundle::new(
+            &mut ancestry,
+            pos.into(),
+            handle.atlas(),
+            clock.tick,
+            &config,
+            rng,
+        ));
     }
 }
 
-fn populate_consumables(mut commands: Commands, handle: Res<GlobalTextureHandle>) {
-    let mut rng = rand::thread_rng();
-
-    for _ in 0..NUM_FOOD {
-        let x = rng.gen_range(-WORLD_W..WORLD_W);
-        let y = rng.gen_range(-WORLD_H..WORLD_H);
-        commands.spawn(FoodBundle::new((x, y), handle.0.clone().unwrap()));
+fn populate_consumables(
+    spawner: Spawner,
+    biomes: Res<BiomeMap>,
+    obstacles_query: Query<&Obstacle>,
+) {
+    let Spawner {
+        mut commands,
+        clock,
+        config,
+        mut rng,
+        handle,
+    } = spawner;
+    let rng = &mut rng.0;
+    for _ in 0..config.num_food {
+        let Some(pos) = get_free_pos(&config, rng, &obstacles_query) else {
+            warn!("No room outside the obstacles for food");
+            continue;
+        };
+        commands.spawn(FoodBundle::new(
+            pos.into(),
+            handle.atlas(),
+            clock.tick,
+            &config,
+            &biomes,
+        ));
     }
-    for _ in 0..NUM_POISON {
-        let x = rng.gen_range(-WORLD_W..WORLD_W);
-        let y = rng.gen_range(-WORLD_H..WORLD_H);
-        commands.spawn(PoisonBundle::new((x, y), handle.0.clone().unwrap()));
+    for _ in 0..config.num_poison {
+        let Some(pos) = get_free_pos(&config, rng, &obstacles_query) else {
+            warn!("No room outside the obstacles for poison");
+            continue;
+        };
+        commands.spawn(PoisonBundle::new(
+            pos.into(),
+            handle.atlas(),
+            clock.tick,
+            &config,
+        ));
     }
 }
 
-fn start_simulation(mut next_state: ResMut<NextState<SimState>>) {
+fn start_simulation(mut commands: Commands, mut next_state: ResMut<NextState<SimState>>) {
+    commands.remove_resource::<PendingSnapshot>();
     next_state.set(SimState::Simulating);
 }